@@ -1,99 +1,610 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use log::info;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, TableLike, Value};
 
-#[derive(Serialize, Deserialize, Clone)]
+pub mod discovery;
+mod migrate;
+pub mod presets;
+mod validate;
+
+pub use validate::ConfigError;
+
+const DEFAULT_SAMPLE_INTERVAL_MS: u64 = 2000;
+const DEFAULT_READ_TIMEOUT_MS: u64 = 10000;
+const DEFAULT_TRANSPORT_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_MODBUS_MAX_GAP: u16 = 0;
+
+/// RoastCraft machine config, top level keys must come before any [table]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Config {
+    /// schema version, older files are migrated when loaded
     pub version: String,
     pub brand: String,
     pub model: String,
-    pub temperature_unit: String,
+    /// unit of channels without unit
+    pub temperature_unit: TemperatureUnit,
+    /// default 2000, 100 ~ 60000
+    pub sample_interval_ms: Option<u64>,
+    /// default 10000, timeout of one whole device read
+    pub read_timeout_ms: Option<u64>,
+    /// default false, reload when this file changes while the device is off
+    pub watch_config: Option<bool>,
+    /// temperatures announced when BT reaches them
     pub alarms: Vec<i32>,
+    /// serial device, has priority over tcp
     pub serial: Option<Serial>,
     pub tcp: Option<Tcp>,
+    /// more devices read at the same time, channels of all devices are merged by channel_id
+    pub device: Option<Vec<Device>>,
+    /// channels set by hand, e.g. gas or airflow
     pub manual_channel: Option<Vec<ManualChannel>>,
+    /// closed-loop control of a writable manual channel, e.g. gas following the BT of a ghost
+    pub pid: Option<Pid>,
 }
 
 impl Config {
     pub fn new() -> Self {
         Self {
-            version: String::new(),
+            version: String::from(migrate::CURRENT_VERSION),
             brand: String::new(),
             model: String::new(),
-            temperature_unit: String::new(),
+            temperature_unit: TemperatureUnit::C,
+            sample_interval_ms: None,
+            read_timeout_ms: None,
+            watch_config: None,
             alarms: Vec::new(),
             serial: None,
             tcp: None,
+            device: None,
             manual_channel: None,
+            pid: None,
+        }
+    }
+
+    // one config per device with serial/tcp and timing of that device only, [serial] or [tcp]
+    // of the top level comes first, then [[device]], names are used in logs and device status
+    pub fn devices(&self) -> Vec<(String, Config)> {
+        let device_config = |serial: &Option<Serial>, tcp: &Option<Tcp>| Config {
+            serial: serial.clone(),
+            // serial has priority over tcp
+            tcp: if serial.is_some() { None } else { tcp.clone() },
+            device: None,
+            ..self.clone()
+        };
+
+        let mut devices = Vec::new();
+
+        match (&self.serial, &self.tcp) {
+            (Some(_), _) => devices.push((
+                String::from("serial"),
+                device_config(&self.serial, &self.tcp),
+            )),
+            (None, Some(_)) => devices.push((String::from("tcp"), device_config(&None, &self.tcp))),
+            (None, None) => {}
+        }
+
+        if let Some(device) = &self.device {
+            for (i, d) in device.iter().enumerate() {
+                let mut c = device_config(&d.serial, &d.tcp);
+                c.sample_interval_ms = d.sample_interval_ms.or(self.sample_interval_ms);
+                c.read_timeout_ms = d.read_timeout_ms.or(self.read_timeout_ms);
+                devices.push((d.name.clone().unwrap_or(format!("device[{}]", i)), c));
+            }
+        }
+
+        devices
+    }
+
+    // channel ids of all devices, in the order of devices()
+    pub fn channel_ids(&self) -> Vec<String> {
+        self.devices()
+            .iter()
+            .flat_map(|(_, c)| c.device_channel_ids())
+            .collect()
+    }
+
+    // channel ids of [serial] or [tcp], same priority as devices::new_device
+    fn device_channel_ids(&self) -> Vec<String> {
+        match (&self.serial, &self.tcp) {
+            (Some(serial), _) => match (&serial.modbus, &serial.ta612c) {
+                (Some(modbus), _) => modbus.slave.iter().map(|s| s.channel_id.clone()).collect(),
+                (None, Some(ta612c)) => ta612c
+                    .channel
+                    .iter()
+                    .map(|c| c.channel_id.clone())
+                    .collect(),
+                (None, None) => Vec::new(),
+            },
+            (None, Some(tcp)) => match (&tcp.modbus, &tcp.http) {
+                (Some(modbus), _) => modbus.slave.iter().map(|s| s.channel_id.clone()).collect(),
+                (None, Some(http)) => http.channel.iter().map(|c| c.channel_id.clone()).collect(),
+                (None, None) => Vec::new(),
+            },
+            (None, None) => Vec::new(),
+        }
+    }
+
+    // name of the device of Config::devices() a manual channel is written through
+    pub fn write_device(&self, target: &ModbusWrite) -> Option<String> {
+        self.devices()
+            .into_iter()
+            .filter(|(_, c)| c.has_modbus())
+            .find(|(name, _)| target.device.is_none() || target.device.as_ref() == Some(name))
+            .map(|(name, _)| name)
+    }
+
+    // [serial.modbus] or [tcp.modbus], same priority as devices::new_device
+    fn has_modbus(&self) -> bool {
+        match (&self.serial, &self.tcp) {
+            (Some(serial), _) => serial.modbus.is_some(),
+            (None, Some(tcp)) => tcp.modbus.is_some(),
+            (None, None) => false,
+        }
+    }
+
+    // channels without unit are temperatures
+    pub fn channel_unit(&self, unit: &Option<String>) -> String {
+        unit.clone().unwrap_or(self.temperature_unit.to_string())
+    }
+
+    pub fn sample_interval(&self) -> Duration {
+        Duration::from_millis(
+            self.sample_interval_ms
+                .unwrap_or(DEFAULT_SAMPLE_INTERVAL_MS),
+        )
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms.unwrap_or(DEFAULT_READ_TIMEOUT_MS))
+    }
+}
+
+// 1 based line of a byte offset in toml source
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+fn parse_error(config_file_name: &str, source: &str, e: toml::de::Error) -> String {
+    match e.span() {
+        Some(span) => format!(
+            "Failed to parse {config_file_name} \nline {} : {}",
+            line_of(source, span.start),
+            e.message()
+        ),
+        None => format!("Failed to parse {config_file_name} \n{}", e.message()),
+    }
+}
+
+// read, migrate, parse and validate a config file, error message is ready to show to user,
+// returns the config and the source it was parsed from, which differs from the file when migrated
+fn read(config_file_name: &str) -> Result<(Config, String, Vec<ConfigError>), String> {
+    let mut toml_content = String::new();
+
+    match File::open(config_file_name) {
+        Ok(mut file) => match file.read_to_string(&mut toml_content) {
+            Ok(_) => {}
+            Err(_) => return Err(format!("Failed to read {config_file_name}")),
+        },
+        Err(_) => return Err(format!("Failed to open {config_file_name}")),
+    }
+
+    let table = toml::from_str::<toml::Table>(&toml_content)
+        .map_err(|e| parse_error(config_file_name, &toml_content, e))?;
+
+    let (source, mut errors) = migrate::migrate(&toml_content, &table)
+        .map_err(|e| format!("Failed to load {config_file_name} \n{}", e))?;
+
+    let c =
+        toml::from_str::<Config>(&source).map_err(|e| parse_error(config_file_name, &source, e))?;
+
+    errors.extend(c.validate());
+    // key paths are the same before and after migration, lines are those of the file
+    validate::locate(&mut errors, &toml_content);
+
+    Ok((c, source, errors))
+}
+
+// the config is returned with its validation errors, see ConfigError::fatal
+pub fn load(config_file_name: &str) -> Result<(Config, Vec<ConfigError>), String> {
+    read(config_file_name).map(|(c, _, errors)| (c, errors))
+}
+
+// write the migrated config back to its file, the old file is kept as <file>.<old version>.bak,
+// returns false if the file is already of the current version
+pub fn write_migrated(config_file_name: &str) -> Result<bool, String> {
+    let (c, source, _) = read(config_file_name)?;
+    let original = fs::read_to_string(config_file_name)
+        .map_err(|_| format!("Failed to read {config_file_name}"))?;
+    if source == original {
+        return Ok(false);
+    }
+
+    let old_version = original
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|t| t.get("version").and_then(|v| v.as_str()).map(String::from))
+        .unwrap_or(String::from("v1"));
+    let backup = format!("{config_file_name}.{old_version}.bak");
+
+    fs::copy(config_file_name, &backup)
+        .map_err(|e| format!("Failed to back up {config_file_name} : {}", e))?;
+    fs::write(config_file_name, source)
+        .map_err(|e| format!("Failed to write {config_file_name} : {}", e))?;
+
+    info!(
+        "{} migrated to {}, backup {}",
+        config_file_name, c.version, backup
+    );
+    Ok(true)
+}
+
+// validate and write the config, the previous file is kept as <file>.bak,
+// written to <file>.tmp first and renamed, a crash never leaves a half written config,
+// only changed values of the previous file are rewritten, its comments and layout are kept
+pub fn save(config_file_name: &str, config: &Config) -> Result<Vec<ConfigError>, String> {
+    // the struct is always of the current schema
+    let mut config = config.clone();
+    config.version = String::from(migrate::CURRENT_VERSION);
+
+    let new = toml::to_string(&config)
+        .map_err(|e| format!("Failed to serialize {config_file_name} : {}", e))?
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("Failed to serialize {config_file_name} : {}", e))?;
+
+    let mut document = fs::read_to_string(config_file_name)
+        .ok()
+        .and_then(|existing| existing.parse::<DocumentMut>().ok())
+        .unwrap_or_default();
+    merge(document.as_table_mut(), new.as_table());
+    let content = document.to_string();
+
+    // validate what is written, errors carry the lines of the new file
+    let written = toml::from_str::<Config>(&content)
+        .map_err(|e| parse_error(config_file_name, &content, e))?;
+    let mut errors = written.validate();
+    validate::locate(&mut errors, &content);
+    if has_fatal(&errors) {
+        return Err(errors_message(config_file_name, &errors));
+    }
+
+    let tmp = format!("{config_file_name}.tmp");
+    File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .map_err(|e| format!("Failed to write {tmp} : {}", e))?;
+
+    if Path::new(config_file_name).exists() {
+        let backup = format!("{config_file_name}.bak");
+        fs::copy(config_file_name, &backup)
+            .map_err(|e| format!("Failed to back up {config_file_name} : {}", e))?;
+    }
+
+    fs::rename(&tmp, config_file_name)
+        .map_err(|e| format!("Failed to write {config_file_name} : {}", e))?;
+
+    info!("config saved : {}", config_file_name);
+    Ok(errors)
+}
+
+// bring table to the keys and values of new, inline tables and dotted keys stay as they are
+fn merge(table: &mut dyn TableLike, new: &dyn TableLike) {
+    let removed: Vec<String> = table
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !new.contains_key(key))
+        .collect();
+    for key in removed {
+        table.remove(&key);
+    }
+
+    for (key, item) in new.iter() {
+        match table.get_mut(key) {
+            Some(existing) => merge_item(existing, item),
+            None => {
+                table.insert(key, detach(item));
+            }
+        }
+    }
+}
+
+fn merge_item(existing: &mut Item, new: &Item) {
+    if let (Some(table), Some(new_table)) = (existing.as_table_like_mut(), new.as_table_like()) {
+        return merge(table, new_table);
+    }
+
+    // [[array]] or inline array of tables, table by table
+    if let (Some(len), Some(new_len)) = (tables_len(existing), tables_len(new)) {
+        for i in 0..new_len {
+            match existing.get_mut(i) {
+                Some(table) => merge_item(table, &new[i]),
+                None => match existing {
+                    Item::ArrayOfTables(array) => {
+                        if let Item::Table(table) = detach(&new[i]) {
+                            array.push(table);
+                        }
+                    }
+                    Item::Value(Value::Array(array)) => {
+                        if let Ok(value) = detach(&new[i]).into_value() {
+                            array.push(value);
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+        for i in (new_len..len).rev() {
+            match existing {
+                Item::ArrayOfTables(array) => array.remove(i),
+                Item::Value(Value::Array(array)) => {
+                    array.remove(i);
+                }
+                _ => {}
+            }
+        }
+        return;
+    }
+
+    match (existing.as_value_mut(), new.as_value()) {
+        (Some(value), Some(new_value)) => {
+            if !same_value(value, new_value) {
+                let decor = value.decor().clone();
+                *value = new_value.clone();
+                *value.decor_mut() = decor;
+            }
+        }
+        _ => *existing = detach(new),
+    }
+}
+
+fn tables_len(item: &Item) -> Option<usize> {
+    match item {
+        Item::ArrayOfTables(array) => Some(array.len()),
+        Item::Value(Value::Array(array))
+            if !array.is_empty() && array.iter().all(|v| v.is_inline_table()) =>
+        {
+            Some(array.len())
+        }
+        _ => None,
+    }
+}
+
+// same value whatever its formatting, 2 and 2.0 too
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Float(b)) | (Value::Float(b), Value::Integer(a)) => {
+            *a.value() as f64 == *b.value()
+        }
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same_value(a, b))
+        }
+        (Value::InlineTable(a), Value::InlineTable(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same_value(a, b)))
         }
+        _ => false,
     }
 }
 
+// copy of an item of new without its place in new, added tables follow the tables around them
+fn detach(item: &Item) -> Item {
+    match item {
+        Item::Table(table) => {
+            let mut detached = Table::new();
+            detached.set_implicit(table.is_implicit());
+            for (key, item) in table.iter() {
+                detached.insert(key, detach(item));
+            }
+            Item::Table(detached)
+        }
+        Item::ArrayOfTables(array) => {
+            let mut detached = ArrayOfTables::new();
+            for table in array.iter() {
+                if let Item::Table(table) = detach(&Item::Table(table.clone())) {
+                    detached.push(table);
+                }
+            }
+            Item::ArrayOfTables(detached)
+        }
+        _ => item.clone(),
+    }
+}
+
+// json schema of the config file, for editors like Taplo / Even Better TOML
+pub fn schema() -> String {
+    serde_json::to_string_pretty(&schema_for!(Config)).unwrap_or_default()
+}
+
+// validation errors for dialogs and logs, one error per line
+pub fn errors_message(config_file_name: &str, errors: &[ConfigError]) -> String {
+    let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    format!("Invalid {config_file_name} \n{}", lines.join("\n"))
+}
+
+pub fn has_fatal(errors: &[ConfigError]) -> bool {
+    errors.iter().any(|e| e.fatal)
+}
+
 // LEVEL 1
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Serial {
+    /// e.g. COM4 or /dev/ttyUSB0, may be empty when usb is given
+    #[serde(default)]
     pub port: String,
-    pub baud_rate: u16,
-    pub data_bits: u16,
-    pub parity: String,
-    pub stop_bits: u16,
+    /// find the port by usb identity, resolved on every connect
+    pub usb: Option<Usb>,
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// default 1000, timeout of one serial read/write
+    pub timeout_ms: Option<u64>,
+    /// either modbus or ta612c
     pub modbus: Option<Modbus>,
     pub ta612c: Option<Ta612c>,
 }
 
+impl Serial {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.unwrap_or(DEFAULT_TRANSPORT_TIMEOUT_MS))
+    }
+}
+
 // LEVEL 1
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Tcp {
     pub ip: String,
     pub port: u16,
+    /// default 1000, timeout of connect and one socket read/write
+    pub timeout_ms: Option<u64>,
+    /// either modbus or http, modbus has priority
     pub modbus: Option<Modbus>,
     pub http: Option<Http>,
 }
 
+impl Tcp {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.unwrap_or(DEFAULT_TRANSPORT_TIMEOUT_MS))
+    }
+}
+
+// LEVEL 1
+/// a device read in its own task, either serial or tcp
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Device {
+    /// shown in logs and device status, default device[n]
+    pub name: Option<String>,
+    /// default sample_interval_ms of the top level
+    pub sample_interval_ms: Option<u64>,
+    /// default read_timeout_ms of the top level
+    pub read_timeout_ms: Option<u64>,
+    pub serial: Option<Serial>,
+    pub tcp: Option<Tcp>,
+}
+
 // LEVEL 2
-#[derive(Serialize, Deserialize, Clone)]
+/// all given fields must match exactly one port, by_id has priority over the others
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Usb {
+    /// usb vendor id, e.g. 0x1a86
+    pub vid: Option<u16>,
+    /// usb product id, e.g. 0x7523
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    /// linux only, /dev/serial/by-id/usb-...
+    pub by_id: Option<String>,
+}
+
+impl fmt::Display for Usb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(by_id) = &self.by_id {
+            parts.push(format!("by_id={}", by_id));
+        }
+        if let Some(vid) = self.vid {
+            parts.push(format!("vid={:04x}", vid));
+        }
+        if let Some(pid) = self.pid {
+            parts.push(format!("pid={:04x}", pid));
+        }
+        if let Some(serial_number) = &self.serial_number {
+            parts.push(format!("serial_number={}", serial_number));
+        }
+        write!(f, "usb {}", parts.join(" "))
+    }
+}
+
+// LEVEL 2
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Ta612c {
+    /// 1 ~ 4 channels, in the order of the thermometer inputs
     pub channel: Vec<Channel>,
 }
 
 // LEVEL 2
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Modbus {
-    pub protocol: String,
+    pub protocol: ModbusProtocol,
+    /// default 0, unused registers allowed between channels read by one request
+    pub max_gap: Option<u16>,
+    /// default and most 125, 123 for modbus-tcp, 61 for modbus-ascii,
+    /// registers read by one request, 1 reads every channel on its own
+    pub max_block: Option<u16>,
     pub slave: Vec<Slave>,
 }
 
+impl Modbus {
+    pub fn max_gap(&self) -> u16 {
+        self.max_gap.unwrap_or(DEFAULT_MODBUS_MAX_GAP)
+    }
+
+    pub fn max_block(&self) -> u16 {
+        self.max_block.unwrap_or(self.protocol.max_block())
+    }
+}
+
 // LEVEL 2
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Http {
     pub channel: Vec<Channel>,
 }
 
 // LEVEL 3
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Channel {
+    /// unique, BT and ET are bean and exhaust temperature
     pub channel_id: String,
     pub label: String,
+    /// default temperature_unit
+    pub unit: Option<String>,
+    /// hex color, e.g. #ff0000
     pub color: String,
+    /// hex color of rate of rise, BT only
     pub ror_color: Option<String>,
 }
 
 // LEVEL 3
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Slave {
     pub channel_id: String,        // Channel
     pub label: String,             // Channel
+    pub unit: Option<String>,      // Channel
     pub color: String,             // Channel
     pub ror_color: Option<String>, // Channel
+    /// unit id, 1 ~ 247
     pub id: u16,
+    /// 3 = holding registers, 4 = input registers
     pub function: u16,
+    /// register address, e.g. 18176 = 4700h
     pub registry: u16,
+    /// value = register / divisor, e.g. 1, 10, 100
     pub divisor: u16,
-    pub decode_type: String,
+    pub decode_type: DecodeType,
+    /// default big
+    pub byte_order: Option<ByteOrder>,
+    /// default big, for 32 bit types
+    pub word_order: Option<ByteOrder>,
 }
 
 // LEVEL 1
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ManualChannel {
     pub channel_id: String, // Channel
     pub label: String,      // Channel
@@ -103,4 +614,381 @@ pub struct ManualChannel {
     pub max: u16,
     pub step: u16,
     pub default_value: u16,
+    /// write the value to the roaster, see set_manual_channel
+    pub modbus: Option<ModbusWrite>,
+}
+
+// LEVEL 2
+/// a holding register the value of a manual channel is written to, and read back from
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ModbusWrite {
+    /// name of a modbus device of [[device]], "serial" or "tcp", default the first modbus device
+    pub device: Option<String>,
+    /// unit id, 1 ~ 247
+    pub id: u16,
+    /// 6 = write single register, 16 = write multiple registers
+    pub function: u16,
+    /// register address
+    pub registry: u16,
+    /// register = value * divisor, default 1
+    pub divisor: Option<u16>,
+    /// default u16, 32 bit types need function 16
+    pub decode_type: Option<DecodeType>,
+    /// default big
+    pub byte_order: Option<ByteOrder>,
+    /// default big, for 32 bit types
+    pub word_order: Option<ByteOrder>,
+    /// values below are written as min, default min of the channel
+    pub min: Option<f64>,
+    /// values above are written as max, default max of the channel
+    pub max: Option<f64>,
+}
+
+impl ModbusWrite {
+    pub fn decode_type(&self) -> DecodeType {
+        self.decode_type.unwrap_or(DecodeType::U16)
+    }
+
+    // divisor = 0 makes no sense, treat it as 1
+    pub fn divisor(&self) -> u16 {
+        self.divisor.unwrap_or(1).max(1)
+    }
+
+    // clamp to min ~ max, or to the range of the manual channel
+    pub fn clamp(&self, mc: &ManualChannel, value: f64) -> f64 {
+        let min = self.min.unwrap_or(mc.min as f64);
+        let max = self.max.unwrap_or(mc.max as f64).max(min);
+        value.clamp(min, max)
+    }
+}
+
+// LEVEL 1
+/// output = kp * error + ki * integral of error + kd * derivative of measurement, in seconds
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Pid {
+    /// channel following the setpoint, default BT
+    pub input: Option<String>,
+    /// channel_id of a manual channel with [manual_channel.modbus]
+    pub output: String,
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    /// default min of the output channel
+    pub output_min: Option<f64>,
+    /// default max of the output channel
+    pub output_max: Option<f64>,
+    /// default sample_interval_ms, least time between two outputs, 100 ~ 60000
+    pub sample_time_ms: Option<u64>,
+    /// setpoint when armed without a ghost, ramps from the input at arming
+    pub segment: Option<Vec<Segment>>,
+}
+
+impl Pid {
+    pub fn input(&self) -> String {
+        self.input.clone().unwrap_or(String::from("BT"))
+    }
+}
+
+// LEVEL 2
+/// ramp to target, then hold it
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Segment {
+    pub target: f64,
+    /// seconds from the previous target to this one, 0 steps at once
+    pub ramp_s: u64,
+    /// seconds target is held
+    pub soak_s: u64,
+}
+
+// ENUMS
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum TemperatureUnit {
+    C,
+    F,
+}
+
+impl fmt::Display for TemperatureUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemperatureUnit::C => write!(f, "C"),
+            TemperatureUnit::F => write!(f, "F"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+// schema of enums written as toml integers, derive would describe the variant names
+fn integer_enum_schema(values: &[u8]) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Integer.into()),
+        enum_values: Some(values.iter().map(|v| (*v).into()).collect()),
+        ..Default::default()
+    }
+    .into()
+}
+
+// toml integer 5 ~ 8
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+impl TryFrom<u8> for DataBits {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            5 => Ok(DataBits::Five),
+            6 => Ok(DataBits::Six),
+            7 => Ok(DataBits::Seven),
+            8 => Ok(DataBits::Eight),
+            _ => Err(format!(
+                "data_bits {} is not supported, use 5, 6, 7 or 8",
+                value
+            )),
+        }
+    }
+}
+
+impl JsonSchema for DataBits {
+    fn schema_name() -> String {
+        String::from("DataBits")
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        integer_enum_schema(&[5, 6, 7, 8])
+    }
+}
+
+impl From<DataBits> for u8 {
+    fn from(value: DataBits) -> Self {
+        match value {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        }
+    }
+}
+
+// toml integer 1 or 2
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+impl TryFrom<u8> for StopBits {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(StopBits::One),
+            2 => Ok(StopBits::Two),
+            _ => Err(format!("stop_bits {} is not supported, use 1 or 2", value)),
+        }
+    }
+}
+
+impl JsonSchema for StopBits {
+    fn schema_name() -> String {
+        String::from("StopBits")
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        integer_enum_schema(&[1, 2])
+    }
+}
+
+impl From<StopBits> for u8 {
+    fn from(value: StopBits) -> Self {
+        match value {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum ModbusProtocol {
+    #[serde(rename = "modbus-rtu")]
+    Rtu, // serial
+    #[serde(rename = "modbus-ascii")]
+    Ascii, // serial
+    #[serde(rename = "modbus-tcp")]
+    Tcp, // tcp
+    #[serde(rename = "rtu-over-tcp")]
+    RtuOverTcp, // tcp, rtu frames in a tcp stream, e.g. serial device servers
+    #[serde(rename = "udp")]
+    Udp, // tcp, rtu frames in udp datagrams
+}
+
+impl ModbusProtocol {
+    // most registers per request whose response frame fits the 255 bytes rmodbus can size,
+    // a read holding/input registers response carries 125 at most
+    pub fn max_block(&self) -> u16 {
+        match self {
+            // unit id, function, byte count, 2 bytes per register, crc
+            ModbusProtocol::Rtu | ModbusProtocol::RtuOverTcp | ModbusProtocol::Udp => 125,
+            // 7 bytes MBAP header, function, byte count, 2 bytes per register
+            ModbusProtocol::Tcp => 123,
+            // ':', 2 characters per byte of the rtu frame with lrc instead of crc, CR LF
+            ModbusProtocol::Ascii => 61,
+        }
+    }
+}
+
+impl fmt::Display for ModbusProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModbusProtocol::Rtu => write!(f, "modbus-rtu"),
+            ModbusProtocol::Ascii => write!(f, "modbus-ascii"),
+            ModbusProtocol::Tcp => write!(f, "modbus-tcp"),
+            ModbusProtocol::RtuOverTcp => write!(f, "rtu-over-tcp"),
+            ModbusProtocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodeType {
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl DecodeType {
+    // number of 16 bit registers
+    pub fn register_count(&self) -> u16 {
+        match self {
+            DecodeType::U16 | DecodeType::I16 => 1,
+            DecodeType::U32 | DecodeType::I32 | DecodeType::F32 => 2,
+        }
+    }
+}
+
+// order of bytes in a register, or of registers in a 32 bit value
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r##"# my roaster
+version = "v2"
+model = "test" # model before brand
+brand = "test"
+temperature_unit = "C"
+read_timeout_ms = 5000 # slow device
+alarms = []
+
+[serial]
+port = "COM3" # usb adapter
+baud_rate = 9600
+data_bits = 8
+parity = "none"
+stop_bits = 1
+
+[serial.ta612c]
+
+[[serial.ta612c.channel]]
+channel_id = "BT"
+label = "BT"
+color = "#ff0000"
+"##;
+
+    // a config file of its own per test, tests run in parallel
+    fn config_file(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("roastcraft_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("roastcraft.toml");
+        fs::write(&file, SOURCE).unwrap();
+        file.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn save_unchanged() {
+        let file = config_file("save_unchanged");
+        let (config, _) = load(&file).unwrap();
+        save(&file, &config).unwrap();
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), SOURCE);
+        assert_eq!(fs::read_to_string(format!("{file}.bak")).unwrap(), SOURCE);
+    }
+
+    #[test]
+    fn save_keeps_comments_and_order() {
+        let file = config_file("save_keeps_comments_and_order");
+        let (mut config, _) = load(&file).unwrap();
+        config.brand = String::from("new");
+        config.read_timeout_ms = None;
+        if let Some(serial) = config.serial.as_mut() {
+            serial.port = String::from("COM4");
+        }
+        save(&file, &config).unwrap();
+
+        let expected = SOURCE
+            .replace("brand = \"test\"", "brand = \"new\"")
+            .replace("read_timeout_ms = 5000 # slow device\n", "")
+            .replace(
+                "port = \"COM3\" # usb adapter",
+                "port = \"COM4\" # usb adapter",
+            );
+        assert_eq!(fs::read_to_string(&file).unwrap(), expected);
+    }
+
+    #[test]
+    fn save_adds_and_removes_tables() {
+        let file = config_file("save_adds_and_removes_tables");
+        let (mut config, _) = load(&file).unwrap();
+        if let Some(ta612c) = config.serial.as_mut().and_then(|s| s.ta612c.as_mut()) {
+            let mut et = ta612c.channel[0].clone();
+            et.channel_id = String::from("ET");
+            et.label = String::from("ET");
+            ta612c.channel.push(et);
+        }
+        save(&file, &config).unwrap();
+
+        let (saved, _) = load(&file).unwrap();
+        assert_eq!(saved.channel_ids(), vec!["BT", "ET"]);
+        let content = fs::read_to_string(&file).unwrap();
+        assert!(
+            content.starts_with(&SOURCE[..SOURCE.len() - 1]),
+            "{}",
+            content
+        );
+
+        config
+            .serial
+            .as_mut()
+            .unwrap()
+            .ta612c
+            .as_mut()
+            .unwrap()
+            .channel
+            .pop();
+        save(&file, &config).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), SOURCE);
+    }
 }
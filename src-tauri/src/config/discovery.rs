@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// where the config file is looked for, first match wins :
+//
+// 1. --config cli argument
+// 2. ROASTCRAFT_CONFIG environment variable
+// 3. last used config, remembered in the app config dir
+// 4. roastcraft.toml in the current dir
+// 5. roastcraft.toml in the dir of the executable
+// 6. roastcraft.toml in the app config dir
+//
+// 1 and 2 are used even if the file is missing, the user asked for it
+
+use log::{info, warn};
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CONFIG_FILE: &str = "roastcraft.toml";
+pub const CONFIG_ENV: &str = "ROASTCRAFT_CONFIG";
+
+// holds the path of the last config loaded with load_config
+const LAST_CONFIG_FILE: &str = "last_config";
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigFile {
+    pub path: String,
+    pub brand: Option<String>,
+    pub model: Option<String>,
+    pub last_used: bool,
+}
+
+fn last_used(config_dir: Option<&Path>) -> Option<PathBuf> {
+    let content = fs::read_to_string(config_dir?.join(LAST_CONFIG_FILE)).ok()?;
+    let path = PathBuf::from(content.trim());
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+// config_dir is None in headless mode, there is no tauri app to resolve it
+pub fn find(cli: Option<&str>, config_dir: Option<&Path>) -> PathBuf {
+    if let Some(path) = cli {
+        info!("config from cli argument : {}", path);
+        return PathBuf::from(path);
+    }
+
+    if let Ok(path) = env::var(CONFIG_ENV) {
+        if !path.trim().is_empty() {
+            info!("config from {} : {}", CONFIG_ENV, path);
+            return PathBuf::from(path.trim());
+        }
+    }
+
+    let mut candidates: Vec<(PathBuf, &str)> = Vec::new();
+
+    // a config picked in Settings wins over the roastcraft.toml installed next to the app,
+    // a missing one falls through to the default files
+    if let Some(path) = last_used(config_dir) {
+        candidates.push((path, "last used"));
+    }
+    candidates.push((PathBuf::from(DEFAULT_CONFIG_FILE), "current dir"));
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        candidates.push((exe_dir.join(DEFAULT_CONFIG_FILE), "executable dir"));
+    }
+    if let Some(dir) = config_dir {
+        candidates.push((dir.join(DEFAULT_CONFIG_FILE), "app config dir"));
+    }
+
+    for (path, source) in &candidates {
+        if path.is_file() {
+            info!("config from {} : {:?}", source, path);
+            return path.clone();
+        }
+    }
+
+    // nothing found, the load error names the classic location
+    warn!(
+        "no config found in {}",
+        candidates
+            .iter()
+            .map(|(path, _)| path.to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    PathBuf::from(DEFAULT_CONFIG_FILE)
+}
+
+pub fn remember(config_dir: &Path, path: &str) -> Result<(), String> {
+    let absolute =
+        fs::canonicalize(path).map_err(|e| format!("Failed to resolve {} : {}", path, e))?;
+
+    fs::create_dir_all(config_dir)
+        .and_then(|_| {
+            fs::write(
+                config_dir.join(LAST_CONFIG_FILE),
+                absolute.to_string_lossy().as_bytes(),
+            )
+        })
+        .map_err(|e| format!("Failed to remember last config : {}", e))
+}
+
+// toml files in the app config dir, brand and model are None if the file does not parse
+pub fn list(config_dir: &Path) -> Vec<ConfigFile> {
+    let last = last_used(Some(config_dir));
+
+    let mut files: Vec<ConfigFile> = match fs::read_dir(config_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .map(|p| {
+                let table = fs::read_to_string(&p)
+                    .ok()
+                    .and_then(|s| s.parse::<toml::Table>().ok());
+                let field = |key: &str| {
+                    table
+                        .as_ref()
+                        .and_then(|t| t.get(key))
+                        .and_then(|v| v.as_str())
+                        .map(String::from)
+                };
+
+                ConfigFile {
+                    path: p.to_string_lossy().to_string(),
+                    brand: field("brand"),
+                    model: field("model"),
+                    last_used: last.is_some() && fs::canonicalize(&p).ok() == last,
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
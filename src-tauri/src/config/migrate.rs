@@ -0,0 +1,413 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// config files are migrated on a toml_edit document, so comments and layout survive a write back
+//
+// v1 : settings were loosely checked, unknown values fell back to defaults
+//      parity in any case, unknown parity = none
+//      data_bits other than 5 ~ 8 = 8, stop_bits other than 2 = 1
+//      serial protocol other than modbus-rtu = modbus-ascii, tcp protocol ignored
+//      every slave was read from holding registers as u16 / 10, function, divisor and
+//      decode_type were ignored, v2 uses them and values change when they differ
+// v2 : typed settings, unknown values are rejected when parsing
+
+use toml::Table;
+use toml_edit::{DocumentMut, Value};
+
+use super::validate;
+use super::ConfigError;
+
+pub const CURRENT_VERSION: &str = "v2";
+
+// one value rewritten by a migration, or only a warning when value is None
+struct Edit {
+    path: String,
+    value: Option<Value>,
+    note: String,
+}
+
+fn string(table: &Table, key: &str) -> Option<String> {
+    table.get(key).and_then(|v| v.as_str()).map(String::from)
+}
+
+fn sub<'a>(table: &'a Table, key: &str) -> Option<&'a Table> {
+    table.get(key).and_then(|v| v.as_table())
+}
+
+// normalize a string value to one of allowed, case insensitive, or fall back to default
+fn one_of(
+    edits: &mut Vec<Edit>,
+    table: &Table,
+    path: &str,
+    key: &str,
+    allowed: &[&str],
+    default: &str,
+) {
+    let value = match string(table, key) {
+        Some(value) => value,
+        None => return,
+    };
+
+    let lowercase = value.to_lowercase();
+    let (new_value, note) = if allowed.contains(&lowercase.as_str()) {
+        (
+            lowercase,
+            format!("\"{}\" is written in lowercase now", value),
+        )
+    } else {
+        (
+            default.to_string(),
+            format!("\"{}\" was read as \"{}\" by v1", value, default),
+        )
+    };
+
+    if new_value != value {
+        edits.push(Edit {
+            path: format!("{}.{}", path, key),
+            value: Some(Value::from(new_value)),
+            note,
+        });
+    }
+}
+
+fn integer_in(
+    edits: &mut Vec<Edit>,
+    table: &Table,
+    path: &str,
+    key: &str,
+    allowed: &[i64],
+    default: i64,
+) {
+    if let Some(value) = table.get(key).and_then(|v| v.as_integer()) {
+        if !allowed.contains(&value) {
+            edits.push(Edit {
+                path: format!("{}.{}", path, key),
+                value: Some(Value::from(default)),
+                note: format!("{} was read as {} by v1", value, default),
+            });
+        }
+    }
+}
+
+fn v1_slaves(edits: &mut Vec<Edit>, modbus: &Table, path: &str) {
+    let slaves = match modbus.get("slave").and_then(|v| v.as_array()) {
+        Some(slaves) => slaves,
+        None => return,
+    };
+
+    for (i, slave) in slaves.iter().enumerate() {
+        if let Some(slave) = slave.as_table() {
+            let path = format!("{}.slave[{}]", path, i);
+            let types = ["u16", "i16", "u32", "i32", "f32"];
+            one_of(edits, slave, &path, "decode_type", &types, "u16");
+            one_of(edits, slave, &path, "byte_order", &["big", "little"], "big");
+            one_of(edits, slave, &path, "word_order", &["big", "little"], "big");
+            v1_decoding(edits, slave, &path, &types);
+        }
+    }
+}
+
+// v1 read every slave as u16 / 10 from holding registers, warn about slaves read differently now
+fn v1_decoding(edits: &mut Vec<Edit>, slave: &Table, path: &str, types: &[&str]) {
+    let function = slave.get("function").and_then(|v| v.as_integer());
+    let divisor = slave.get("divisor").and_then(|v| v.as_integer());
+    // as left by one_of
+    let decode_type = string(slave, "decode_type")
+        .map(|t| t.to_lowercase())
+        .filter(|t| types.contains(&t.as_str()))
+        .unwrap_or(String::from("u16"));
+
+    let mut changes = Vec::new();
+    if function == Some(4) {
+        changes.push(String::from("from input registers"));
+    }
+    if decode_type != "u16" {
+        changes.push(format!("as {}", decode_type));
+    }
+    if let Some(divisor) = divisor.filter(|d| *d != 10) {
+        changes.push(format!("divided by {}", divisor.max(1)));
+    }
+
+    if !changes.is_empty() {
+        edits.push(Edit {
+            path: path.to_string(),
+            value: None,
+            note: format!(
+                "v1 read it as u16 / 10 from holding registers, now it is read {}, check its values",
+                changes.join(", ")
+            ),
+        });
+    }
+}
+
+fn v1_to_v2(table: &Table) -> Vec<Edit> {
+    let mut edits = Vec::new();
+
+    if let Some(unit) = string(table, "temperature_unit") {
+        let uppercase = unit.to_uppercase();
+        if uppercase != unit && (uppercase == "C" || uppercase == "F") {
+            edits.push(Edit {
+                path: String::from("temperature_unit"),
+                value: Some(Value::from(uppercase)),
+                note: format!("\"{}\" is written in uppercase now", unit),
+            });
+        }
+    }
+
+    if let Some(serial) = sub(table, "serial") {
+        let parities = ["none", "even", "odd"];
+        one_of(&mut edits, serial, "serial", "parity", &parities, "none");
+        integer_in(&mut edits, serial, "serial", "data_bits", &[5, 6, 7, 8], 8);
+        integer_in(&mut edits, serial, "serial", "stop_bits", &[1, 2], 1);
+
+        if let Some(modbus) = sub(serial, "modbus") {
+            if let Some(protocol) = string(modbus, "protocol") {
+                if protocol != "modbus-rtu" && protocol != "modbus-ascii" {
+                    edits.push(Edit {
+                        path: String::from("serial.modbus.protocol"),
+                        value: Some(Value::from("modbus-ascii")),
+                        note: format!("\"{}\" was read as \"modbus-ascii\" by v1", protocol),
+                    });
+                }
+            }
+            v1_slaves(&mut edits, modbus, "serial.modbus");
+        }
+    }
+
+    if let Some(modbus) = sub(table, "tcp").and_then(|tcp| sub(tcp, "modbus")) {
+        if let Some(protocol) = string(modbus, "protocol") {
+            if protocol != "modbus-tcp" {
+                edits.push(Edit {
+                    path: String::from("tcp.modbus.protocol"),
+                    value: Some(Value::from("modbus-tcp")),
+                    note: format!(
+                        "\"{}\" was ignored by v1, tcp is always modbus-tcp",
+                        protocol
+                    ),
+                });
+            }
+        }
+        v1_slaves(&mut edits, modbus, "tcp.modbus");
+    }
+
+    edits
+}
+
+fn version_number(version: &str) -> Option<u32> {
+    version
+        .strip_prefix('v')
+        .and_then(|n| n.parse::<u32>().ok())
+}
+
+// bring toml source to CURRENT_VERSION, returns the new source and a warning per change,
+// source of the current version is returned unchanged
+pub fn migrate(source: &str, table: &Table) -> Result<(String, Vec<ConfigError>), String> {
+    let current = version_number(CURRENT_VERSION).unwrap_or(0);
+
+    let version = string(table, "version");
+    let number = match &version {
+        Some(v) => version_number(v).ok_or(format!(
+            "Unknown config version \"{}\", expected v1 ~ {}",
+            v, CURRENT_VERSION
+        ))?,
+        // files before versioning are v1 too
+        None => 1,
+    };
+
+    if number > current {
+        return Err(format!(
+            "Config version {} is newer than this RoastCraft supports ({}), please update RoastCraft",
+            version.unwrap_or_default(),
+            CURRENT_VERSION
+        ));
+    }
+    if number == current {
+        return Ok((source.to_string(), Vec::new()));
+    }
+
+    let mut edits = Vec::new();
+    if number <= 1 {
+        edits.extend(v1_to_v2(table));
+    }
+    // later migrations go here, each one from the layout left by the previous
+
+    let mut document = source
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("Failed to migrate : {}", e))?;
+    let mut warnings = Vec::new();
+    for edit in edits {
+        // keys are checked to exist, inline tables and dotted keys are edited in place too
+        if let Some(value) = edit.value {
+            if !validate::set(&mut document, &edit.path, value) {
+                return Err(format!("Failed to migrate {}", edit.path));
+            }
+        }
+        warnings.push(ConfigError {
+            path: edit.path,
+            line: None,
+            message: format!("migrated : {}", edit.note),
+            fatal: false,
+        });
+    }
+
+    match version {
+        Some(_) => {
+            if !validate::set(&mut document, "version", CURRENT_VERSION.into()) {
+                return Err("Failed to migrate version".to_string());
+            }
+        }
+        None => {
+            document.insert("version", toml_edit::value(CURRENT_VERSION));
+        }
+    }
+    warnings.push(ConfigError {
+        path: String::from("version"),
+        line: None,
+        message: format!(
+            "migrated from {} to {}, upgrade the file in Settings to keep the changes",
+            string(table, "version").unwrap_or(String::from("no version")),
+            CURRENT_VERSION
+        ),
+        fatal: false,
+    });
+
+    Ok((document.to_string(), warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> (DocumentMut, Vec<ConfigError>) {
+        let table = source.parse::<Table>().unwrap();
+        let (migrated, warnings) = migrate(source, &table).unwrap();
+        (migrated.parse::<DocumentMut>().unwrap(), warnings)
+    }
+
+    #[test]
+    fn header_tables() {
+        let source = r#"version = "v1"
+[serial]
+port = "COM3"
+parity = "N" # kept
+[serial.modbus]
+protocol = "rtu"
+[[serial.modbus.slave]]
+decode_type = "float"
+"#;
+        let (document, warnings) = run(source);
+
+        assert_eq!(document["version"].as_str(), Some("v2"));
+        assert_eq!(document["serial"]["parity"].as_str(), Some("none"));
+        assert_eq!(
+            document["serial"]["modbus"]["protocol"].as_str(),
+            Some("modbus-ascii")
+        );
+        assert_eq!(
+            document["serial"]["modbus"]["slave"][0]["decode_type"].as_str(),
+            Some("u16")
+        );
+        assert!(document.to_string().contains("parity = \"none\" # kept"));
+        assert_eq!(warnings.len(), 4);
+    }
+
+    #[test]
+    fn inline_and_dotted_tables() {
+        let cases = [
+            "serial = { port = \"COM3\", parity = \"N\", stop_bits = 3 }\n",
+            "serial.port = \"COM3\"\nserial.parity = \"N\"\nserial.stop_bits = 3\n",
+            "[serial]\nport = \"COM3\"\n\"parity\" = \"N\"\nstop_bits = 3\n",
+        ];
+
+        for source in cases {
+            let (document, warnings) = run(source);
+
+            assert_eq!(document["version"].as_str(), Some("v2"), "{}", source);
+            assert_eq!(
+                document["serial"]["parity"].as_str(),
+                Some("none"),
+                "{}",
+                source
+            );
+            assert_eq!(
+                document["serial"]["stop_bits"].as_integer(),
+                Some(1),
+                "{}",
+                source
+            );
+            assert_eq!(
+                document["serial"]["port"].as_str(),
+                Some("COM3"),
+                "{}",
+                source
+            );
+            assert_eq!(warnings.len(), 3, "{}", source);
+        }
+    }
+
+    #[test]
+    fn inline_slaves() {
+        let source = r#"version = "v1"
+[tcp]
+ip = "127.0.0.1"
+modbus = { protocol = "udp", slave = [{ decode_type = "I16" }, { decode_type = "f32", word_order = "LITTLE" }] }
+"#;
+        let (document, _) = run(source);
+        let modbus = &document["tcp"]["modbus"];
+
+        assert_eq!(modbus["protocol"].as_str(), Some("modbus-tcp"));
+        assert_eq!(modbus["slave"][0]["decode_type"].as_str(), Some("i16"));
+        assert_eq!(modbus["slave"][1]["word_order"].as_str(), Some("little"));
+    }
+
+    #[test]
+    fn v1_decoding_changes_warned() {
+        let source = r#"[serial.modbus]
+protocol = "modbus-rtu"
+[[serial.modbus.slave]]
+function = 3
+divisor = 10
+decode_type = "u16"
+[[serial.modbus.slave]]
+function = 4
+divisor = 100
+decode_type = "I16"
+[[serial.modbus.slave]]
+function = 3
+divisor = 1
+decode_type = "unknown"
+"#;
+        let (document, warnings) = run(source);
+        let slaves: Vec<&ConfigError> = warnings
+            .iter()
+            .filter(|w| w.message.contains("v1 read it as u16 / 10"))
+            .collect();
+
+        assert_eq!(slaves.len(), 2);
+        assert_eq!(slaves[0].path, "serial.modbus.slave[1]");
+        assert!(slaves[0].message.ends_with(
+            "now it is read from input registers, as i16, divided by 100, check its values"
+        ));
+        assert_eq!(slaves[1].path, "serial.modbus.slave[2]");
+        assert!(slaves[1].message.contains("now it is read divided by 1,"));
+
+        // only warned, divisor and function are kept
+        assert_eq!(
+            document["serial"]["modbus"]["slave"][1]["divisor"].as_integer(),
+            Some(100)
+        );
+        assert_eq!(
+            document["serial"]["modbus"]["slave"][1]["function"].as_integer(),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn current_version_unchanged() {
+        let source = "version = \"v2\"\n# comment\n[serial]\nparity = \"N\"\n";
+        let table = source.parse::<Table>().unwrap();
+        let (migrated, warnings) = migrate(source, &table).unwrap();
+
+        assert_eq!(migrated, source);
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use log::{error, info};
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::{validate, Config};
+
+// machine configs shipped with the app, vendor presets first,
+// generic examples sharing brand and model with them are hidden from the list
+const PRESETS: [(&str, &str); 4] = [
+    (
+        "kapok/501_inlet.toml",
+        include_str!("../../../machines/kapok/501_inlet.toml"),
+    ),
+    (
+        "tasi/ta612c.toml",
+        include_str!("../../../machines/tasi/ta612c.toml"),
+    ),
+    ("modbus.toml", include_str!("../../../machines/modbus.toml")),
+    ("http.toml", include_str!("../../../machines/http.toml")),
+];
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MachinePreset {
+    pub brand: String,
+    pub model: String,
+    pub file: String,       // path in machines/ of the repo
+    pub connection: String, // serial or tcp, what the port argument means
+    pub channels: Vec<String>,
+}
+
+fn parse(file: &str, source: &str) -> Option<MachinePreset> {
+    match toml::from_str::<Config>(source) {
+        Ok(config) => Some(MachinePreset {
+            brand: config.brand.clone(),
+            model: config.model.clone(),
+            file: file.to_string(),
+            connection: match config.serial {
+                Some(_) => String::from("serial"),
+                None => String::from("tcp"),
+            },
+            channels: config.channel_ids(),
+        }),
+        Err(e) => {
+            // a broken preset is a bug of the release, do not hide the others
+            error!("preset {} is invalid : {}", file, e.message());
+            None
+        }
+    }
+}
+
+pub fn list() -> Vec<MachinePreset> {
+    let mut presets: Vec<MachinePreset> = Vec::new();
+
+    for (file, source) in PRESETS {
+        if let Some(preset) = parse(file, source) {
+            if !presets
+                .iter()
+                .any(|p| p.brand == preset.brand && p.model == preset.model)
+            {
+                presets.push(preset);
+            }
+        }
+    }
+
+    presets
+}
+
+// port is the serial port name for serial presets, ip or ip:port for tcp presets
+fn with_port(source: &str, connection: &str, port: &str) -> Result<String, String> {
+    let result = match connection {
+        "serial" => validate::set_value(source, "serial.port", port.into()),
+        _ => match port.rsplit_once(':') {
+            Some((ip, tcp_port)) => {
+                let tcp_port = tcp_port
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid tcp port {}", tcp_port))?;
+                validate::set_value(source, "tcp.ip", ip.into())
+                    .and_then(|s| validate::set_value(&s, "tcp.port", (tcp_port as i64).into()))
+            }
+            None => validate::set_value(source, "tcp.ip", port.into()),
+        },
+    };
+
+    result.ok_or(format!("Failed to set port of {} preset", connection))
+}
+
+// file name of a config made from a preset, e.g. kapok_501.toml
+fn file_name(brand: &str, model: &str) -> String {
+    let name: String = format!("{}_{}", brand, model)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.toml", name)
+}
+
+// write the preset of brand and model into dir with the given port, an existing file is not overwritten
+pub fn create(dir: &Path, brand: &str, model: &str, port: &str) -> Result<PathBuf, String> {
+    let preset = list()
+        .into_iter()
+        .find(|p| p.brand == brand && p.model == model)
+        .ok_or(format!("Unknown machine preset {} {}", brand, model))?;
+
+    let source = PRESETS
+        .iter()
+        .find(|(file, _)| *file == preset.file)
+        .map(|(_, source)| *source)
+        .ok_or(format!("Missing machine preset {}", preset.file))?;
+
+    let content = if port.trim().is_empty() {
+        source.to_string()
+    } else {
+        with_port(source, &preset.connection, port.trim())?
+    };
+
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?} : {}", dir, e))?;
+
+    let path = dir.join(file_name(brand, model));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|e| format!("Failed to create {:?} : {}", path, e))?;
+
+    info!("config created from preset {} : {:?}", preset.file, path);
+    Ok(path)
+}
@@ -0,0 +1,794 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use toml_edit::{DocumentMut, ImDocument, Item, Value};
+
+use super::{
+    Channel, Config, ManualChannel, Modbus, ModbusProtocol, ModbusWrite, Pid, Serial, Slave, Tcp,
+};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigError {
+    pub path: String,        // toml key path, e.g. serial.modbus.slave[1].decode_type
+    pub line: Option<usize>, // 1 based line in toml file, if found
+    pub message: String,
+    pub fatal: bool, // acquisition refuses to start
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = if self.fatal { "error" } else { "warning" };
+        match self.line {
+            Some(line) => write!(
+                f,
+                "{} line {}, {} : {}",
+                level, line, self.path, self.message
+            ),
+            None => write!(f, "{} {} : {}", level, self.path, self.message),
+        }
+    }
+}
+
+struct Errors(Vec<ConfigError>);
+
+impl Errors {
+    fn fatal(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigError {
+            path: path.into(),
+            line: None,
+            message: message.into(),
+            fatal: true,
+        });
+    }
+
+    fn warning(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigError {
+            path: path.into(),
+            line: None,
+            message: message.into(),
+            fatal: false,
+        });
+    }
+}
+
+// #rgb or #rrggbb
+fn is_hex_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => {
+            (hex.len() == 3 || hex.len() == 6) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+fn check_channel(
+    errors: &mut Errors,
+    path: &str,
+    channel_id: &str,
+    color: &str,
+    ror_color: &Option<String>,
+) {
+    if channel_id.trim().is_empty() {
+        errors.fatal(format!("{}.channel_id", path), "channel_id is empty");
+    }
+    if !is_hex_color(color) {
+        errors.warning(
+            format!("{}.color", path),
+            format!("\"{}\" is not a hex color like \"#ff0000\"", color),
+        );
+    }
+    if let Some(ror_color) = ror_color {
+        if !is_hex_color(ror_color) {
+            errors.warning(
+                format!("{}.ror_color", path),
+                format!("\"{}\" is not a hex color like \"#ff0000\"", ror_color),
+            );
+        }
+    }
+}
+
+fn check_channels(errors: &mut Errors, path: &str, channels: &[Channel]) {
+    for (i, c) in channels.iter().enumerate() {
+        check_channel(
+            errors,
+            &format!("{}[{}]", path, i),
+            &c.channel_id,
+            &c.color,
+            &c.ror_color,
+        );
+    }
+}
+
+// serial and tcp support different protocols
+fn check_modbus(errors: &mut Errors, path: &str, modbus: &Modbus, protocols: &[ModbusProtocol]) {
+    if !protocols.contains(&modbus.protocol) {
+        let names: Vec<String> = protocols.iter().map(|p| p.to_string()).collect();
+        errors.fatal(
+            format!("{}.protocol", path),
+            format!(
+                "protocol \"{}\" is not supported here, use {}",
+                modbus.protocol,
+                names.join(", ")
+            ),
+        );
+    }
+
+    let max_block = modbus.max_block();
+    if max_block == 0 || max_block > modbus.protocol.max_block() {
+        errors.fatal(
+            format!("{}.max_block", path),
+            format!(
+                "{} is out of range 1 ~ {} for {}",
+                max_block,
+                modbus.protocol.max_block(),
+                modbus.protocol
+            ),
+        );
+    }
+
+    if modbus.slave.is_empty() {
+        errors.fatal(format!("{}.slave", path), "at least one slave is required");
+    }
+
+    for (i, slave) in modbus.slave.iter().enumerate() {
+        check_slave(errors, &format!("{}.slave[{}]", path, i), slave);
+    }
+}
+
+fn check_slave(errors: &mut Errors, path: &str, slave: &Slave) {
+    check_channel(
+        errors,
+        path,
+        &slave.channel_id,
+        &slave.color,
+        &slave.ror_color,
+    );
+
+    if slave.id == 0 || slave.id > 247 {
+        errors.fatal(
+            format!("{}.id", path),
+            format!("unit id {} is out of range 1 ~ 247", slave.id),
+        );
+    }
+    if slave.function != 3 && slave.function != 4 {
+        errors.fatal(
+            format!("{}.function", path),
+            format!(
+                "function {} is not supported, use 3 (holding) or 4 (input)",
+                slave.function
+            ),
+        );
+    }
+    if slave.divisor == 0 {
+        errors.fatal(format!("{}.divisor", path), "divisor must not be 0");
+    }
+}
+
+fn check_manual_channel(errors: &mut Errors, path: &str, mc: &ManualChannel) {
+    check_channel(errors, path, &mc.channel_id, &mc.color, &None);
+
+    if mc.min > mc.max {
+        errors.fatal(
+            format!("{}.min", path),
+            format!("min {} is greater than max {}", mc.min, mc.max),
+        );
+    } else {
+        if mc.default_value < mc.min || mc.default_value > mc.max {
+            errors.warning(
+                format!("{}.default_value", path),
+                format!(
+                    "{} is out of range {} ~ {}",
+                    mc.default_value, mc.min, mc.max
+                ),
+            );
+        }
+        if mc.step > mc.max - mc.min {
+            errors.warning(
+                format!("{}.step", path),
+                format!(
+                    "step {} is larger than range {} ~ {}",
+                    mc.step, mc.min, mc.max
+                ),
+            );
+        }
+    }
+}
+
+fn check_modbus_write(errors: &mut Errors, path: &str, target: &ModbusWrite) {
+    if target.id == 0 || target.id > 247 {
+        errors.fatal(
+            format!("{}.id", path),
+            format!("unit id {} is out of range 1 ~ 247", target.id),
+        );
+    }
+    match target.function {
+        6 => {
+            if target.decode_type().register_count() > 1 {
+                errors.fatal(
+                    format!("{}.function", path),
+                    "function 6 writes one register only, use 16 for 32 bit types",
+                );
+            }
+        }
+        16 => {}
+        other => errors.fatal(
+            format!("{}.function", path),
+            format!(
+                "function {} is not supported, use 6 (single) or 16 (multiple)",
+                other
+            ),
+        ),
+    }
+    if target.divisor == Some(0) {
+        errors.fatal(format!("{}.divisor", path), "divisor must not be 0");
+    }
+    if let (Some(min), Some(max)) = (target.min, target.max) {
+        if min > max {
+            errors.fatal(
+                format!("{}.min", path),
+                format!("min {} is greater than max {}", min, max),
+            );
+        }
+    }
+}
+
+fn check_pid(errors: &mut Errors, config: &Config, pid: &Pid) {
+    if !config.channel_ids().contains(&pid.input()) {
+        errors.fatal(
+            "pid.input",
+            format!("no channel \"{}\" to follow", pid.input()),
+        );
+    }
+
+    let output = config
+        .manual_channel
+        .iter()
+        .flatten()
+        .find(|mc| mc.channel_id == pid.output);
+    match output {
+        Some(mc) if mc.modbus.is_some() => {}
+        Some(_) => errors.fatal(
+            "pid.output",
+            format!(
+                "manual channel \"{}\" has no [manual_channel.modbus] to write to",
+                pid.output
+            ),
+        ),
+        None => errors.fatal(
+            "pid.output",
+            format!("no manual channel \"{}\"", pid.output),
+        ),
+    }
+
+    for (key, gain) in [("kp", pid.kp), ("ki", pid.ki), ("kd", pid.kd)] {
+        if gain.is_nan() || gain < 0.0 {
+            errors.fatal(
+                format!("pid.{}", key),
+                format!("{} must be 0 or more", gain),
+            );
+        }
+    }
+
+    if let (Some(min), Some(max)) = (pid.output_min, pid.output_max) {
+        if min > max {
+            errors.fatal(
+                "pid.output_min",
+                format!("output_min {} is greater than output_max {}", min, max),
+            );
+        }
+    }
+
+    if let Some(ms) = pid.sample_time_ms {
+        check_timing(errors, "pid.sample_time_ms", ms);
+    }
+
+    if let Some(segments) = &pid.segment {
+        for (i, segment) in segments.iter().enumerate() {
+            if segment.ramp_s == 0 && segment.soak_s == 0 {
+                errors.warning(
+                    format!("pid.segment[{}]", i),
+                    "ramp_s and soak_s are 0, the setpoint steps to target",
+                );
+            }
+        }
+    }
+}
+
+fn check_timing(errors: &mut Errors, path: &str, ms: u64) {
+    if !(100..=60000).contains(&ms) {
+        errors.fatal(path, format!("{} is out of range 100 ~ 60000", ms));
+    }
+}
+
+// [serial] and [tcp] of the top level or of a [[device]], prefix is "" or "device[n]."
+fn check_device(
+    errors: &mut Errors,
+    prefix: &str,
+    serial: &Option<Serial>,
+    tcp: &Option<Tcp>,
+    read_timeout_ms: u64,
+) {
+    if let Some(serial) = serial {
+        match &serial.usb {
+            Some(usb) => {
+                if usb.vid.is_none()
+                    && usb.pid.is_none()
+                    && usb.serial_number.is_none()
+                    && usb.by_id.is_none()
+                {
+                    errors.fatal(
+                        format!("{}serial.usb", prefix),
+                        "set at least one of vid, pid, serial_number, by_id",
+                    );
+                }
+                if !serial.port.trim().is_empty() {
+                    errors.warning(
+                        format!("{}serial.port", prefix),
+                        "port is ignored, [serial.usb] has priority",
+                    );
+                }
+            }
+            None => {
+                if serial.port.trim().is_empty() {
+                    errors.fatal(
+                        format!("{}serial.port", prefix),
+                        "port is empty, set port or [serial.usb]",
+                    );
+                }
+            }
+        }
+        let timeout_ms = serial.timeout().as_millis() as u64;
+        if timeout_ms < 10 || timeout_ms > read_timeout_ms {
+            errors.fatal(
+                format!("{}serial.timeout_ms", prefix),
+                format!(
+                    "{} is out of range 10 ~ read_timeout_ms ({})",
+                    timeout_ms, read_timeout_ms
+                ),
+            );
+        }
+
+        match (&serial.modbus, &serial.ta612c) {
+            (Some(_), Some(_)) => errors.fatal(
+                format!("{}serial.ta612c", prefix),
+                "[serial.modbus] and [serial.ta612c] cannot be used together, remove one",
+            ),
+            (None, None) => errors.fatal(
+                format!("{}serial", prefix),
+                "either [serial.modbus] or [serial.ta612c] is required",
+            ),
+            _ => {}
+        }
+
+        if let Some(modbus) = &serial.modbus {
+            check_modbus(
+                errors,
+                &format!("{}serial.modbus", prefix),
+                modbus,
+                &[ModbusProtocol::Rtu, ModbusProtocol::Ascii],
+            );
+        }
+
+        if let Some(ta612c) = &serial.ta612c {
+            if ta612c.channel.is_empty() || ta612c.channel.len() > 4 {
+                errors.fatal(
+                    format!("{}serial.ta612c.channel", prefix),
+                    format!(
+                        "ta612c has 1 ~ 4 channels, {} configured",
+                        ta612c.channel.len()
+                    ),
+                );
+            }
+            check_channels(
+                errors,
+                &format!("{}serial.ta612c.channel", prefix),
+                &ta612c.channel,
+            );
+        }
+    }
+
+    if let Some(tcp) = tcp {
+        if tcp.ip.trim().is_empty() {
+            errors.fatal(format!("{}tcp.ip", prefix), "ip is empty");
+        }
+
+        let timeout_ms = tcp.timeout().as_millis() as u64;
+        if timeout_ms < 10 || timeout_ms > read_timeout_ms {
+            errors.fatal(
+                format!("{}tcp.timeout_ms", prefix),
+                format!(
+                    "{} is out of range 10 ~ read_timeout_ms ({})",
+                    timeout_ms, read_timeout_ms
+                ),
+            );
+        }
+
+        if tcp.modbus.is_none() && tcp.http.is_none() {
+            errors.fatal(
+                format!("{}tcp", prefix),
+                "either [tcp.modbus] or [tcp.http] is required",
+            );
+        }
+        if let Some(modbus) = &tcp.modbus {
+            check_modbus(
+                errors,
+                &format!("{}tcp.modbus", prefix),
+                modbus,
+                &[
+                    ModbusProtocol::Tcp,
+                    ModbusProtocol::RtuOverTcp,
+                    ModbusProtocol::Udp,
+                ],
+            );
+        }
+        if let Some(http) = &tcp.http {
+            check_channels(
+                errors,
+                &format!("{}tcp.http.channel", prefix),
+                &http.channel,
+            );
+        }
+    }
+}
+
+impl Config {
+    // semantic checks after parsing, errors carry key paths but no lines, see locate()
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Errors(Vec::new());
+
+        // timing
+        let sample_interval_ms = self.sample_interval().as_millis() as u64;
+        check_timing(&mut errors, "sample_interval_ms", sample_interval_ms);
+
+        let read_timeout_ms = self.read_timeout().as_millis() as u64;
+        check_timing(&mut errors, "read_timeout_ms", read_timeout_ms);
+
+        // devices
+        let has_device = self.device.as_ref().is_some_and(|d| !d.is_empty());
+        match (&self.serial, &self.tcp) {
+            (None, None) if !has_device => {
+                errors.fatal("serial", "either [serial], [tcp] or [[device]] is required")
+            }
+            (Some(_), Some(_)) => errors.warning("tcp", "[tcp] is ignored, [serial] has priority"),
+            _ => {}
+        }
+        check_device(&mut errors, "", &self.serial, &self.tcp, read_timeout_ms);
+
+        if let Some(device) = &self.device {
+            for (i, d) in device.iter().enumerate() {
+                let prefix = format!("device[{}].", i);
+
+                if let Some(ms) = d.sample_interval_ms {
+                    check_timing(&mut errors, &format!("{}sample_interval_ms", prefix), ms);
+                }
+                if let Some(ms) = d.read_timeout_ms {
+                    check_timing(&mut errors, &format!("{}read_timeout_ms", prefix), ms);
+                }
+
+                match (&d.serial, &d.tcp) {
+                    (None, None) => errors.fatal(
+                        format!("device[{}]", i),
+                        "either [device.serial] or [device.tcp] is required",
+                    ),
+                    (Some(_), Some(_)) => errors.fatal(
+                        format!("{}tcp", prefix),
+                        "a device is either serial or tcp, add another [[device]] for tcp",
+                    ),
+                    _ => {}
+                }
+
+                check_device(
+                    &mut errors,
+                    &prefix,
+                    &d.serial,
+                    &d.tcp,
+                    d.read_timeout_ms.unwrap_or(read_timeout_ms),
+                );
+            }
+        }
+
+        // a serial port can be opened only once
+        let mut ports: HashMap<String, String> = HashMap::new();
+        for (prefix, serial, _) in self.sections() {
+            if let Some(serial) = serial.filter(|s| s.usb.is_none()) {
+                let path = format!("{}serial.port", prefix);
+                match ports.get(&serial.port) {
+                    Some(first) => errors.fatal(
+                        path,
+                        format!("port {} is already used by {}", serial.port, first),
+                    ),
+                    None => {
+                        ports.insert(serial.port.clone(), path);
+                    }
+                }
+            }
+        }
+
+        if let Some(manual_channels) = &self.manual_channel {
+            for (i, mc) in manual_channels.iter().enumerate() {
+                let path = format!("manual_channel[{}]", i);
+                check_manual_channel(&mut errors, &path, mc);
+
+                if let Some(target) = &mc.modbus {
+                    check_modbus_write(&mut errors, &format!("{}.modbus", path), target);
+                    if self.write_device(target).is_none() {
+                        errors.fatal(
+                            format!("{}.modbus.device", path),
+                            match &target.device {
+                                Some(name) => format!("{} is not a modbus device", name),
+                                None => "no modbus device to write to".to_string(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(pid) = &self.pid {
+            check_pid(&mut errors, self, pid);
+        }
+
+        // channel ids are keys of samples, they must be unique
+        let mut seen: HashMap<String, String> = HashMap::new();
+        for (path, channel_id) in self.channel_paths() {
+            match seen.get(&channel_id) {
+                Some(first) => errors.fatal(
+                    format!("{}.channel_id", path),
+                    format!(
+                        "duplicate channel_id \"{}\", already used by {}",
+                        channel_id, first
+                    ),
+                ),
+                None => {
+                    seen.insert(channel_id, path);
+                }
+            }
+        }
+
+        errors.0
+    }
+
+    // (key prefix, serial, tcp) of the top level and of each [[device]], serial has priority
+    fn sections(&self) -> Vec<(String, Option<&Serial>, Option<&Tcp>)> {
+        let mut sections = vec![(
+            String::new(),
+            self.serial.as_ref(),
+            self.tcp.as_ref().filter(|_| self.serial.is_none()),
+        )];
+        if let Some(device) = &self.device {
+            for (i, d) in device.iter().enumerate() {
+                sections.push((
+                    format!("device[{}].", i),
+                    d.serial.as_ref(),
+                    d.tcp.as_ref().filter(|_| d.serial.is_none()),
+                ));
+            }
+        }
+        sections
+    }
+
+    // (key path, channel_id) of channels of all devices and manual channels
+    fn channel_paths(&self) -> Vec<(String, String)> {
+        fn add_slaves(prefix: &str, slaves: &[Slave], paths: &mut Vec<(String, String)>) {
+            for (i, s) in slaves.iter().enumerate() {
+                paths.push((format!("{}[{}]", prefix, i), s.channel_id.clone()));
+            }
+        }
+
+        fn add_channels(prefix: &str, channels: &[Channel], paths: &mut Vec<(String, String)>) {
+            for (i, c) in channels.iter().enumerate() {
+                paths.push((format!("{}[{}]", prefix, i), c.channel_id.clone()));
+            }
+        }
+
+        let mut paths = Vec::new();
+
+        for (prefix, serial, tcp) in self.sections() {
+            match (serial, tcp) {
+                (Some(serial), _) => match (&serial.modbus, &serial.ta612c) {
+                    (Some(modbus), _) => add_slaves(
+                        &format!("{}serial.modbus.slave", prefix),
+                        &modbus.slave,
+                        &mut paths,
+                    ),
+                    (None, Some(ta612c)) => add_channels(
+                        &format!("{}serial.ta612c.channel", prefix),
+                        &ta612c.channel,
+                        &mut paths,
+                    ),
+                    (None, None) => {}
+                },
+                (None, Some(tcp)) => match (&tcp.modbus, &tcp.http) {
+                    (Some(modbus), _) => add_slaves(
+                        &format!("{}tcp.modbus.slave", prefix),
+                        &modbus.slave,
+                        &mut paths,
+                    ),
+                    (None, Some(http)) => add_channels(
+                        &format!("{}tcp.http.channel", prefix),
+                        &http.channel,
+                        &mut paths,
+                    ),
+                    (None, None) => {}
+                },
+                (None, None) => {}
+            }
+        }
+
+        if let Some(manual_channels) = &self.manual_channel {
+            for (i, mc) in manual_channels.iter().enumerate() {
+                paths.push((format!("manual_channel[{}]", i), mc.channel_id.clone()));
+            }
+        }
+
+        paths
+    }
+}
+
+// split a segment of a key path, "slave[1]" into ("slave", Some(1))
+fn split_segment(segment: &str) -> (&str, Option<usize>) {
+    match segment.split_once('[') {
+        Some((name, index)) => (name, index.trim_end_matches(']').parse::<usize>().ok()),
+        None => (segment, None),
+    }
+}
+
+// 1 based line of a key path in toml source, the closest table on the path if the key itself is
+// missing, spans of toml_edit cover inline tables, dotted and quoted keys
+pub(super) fn find_line(source: &str, path: &str) -> Option<usize> {
+    let document = ImDocument::parse(source).ok()?;
+    let mut item = document.as_item();
+    let mut span = None;
+
+    for segment in path.split('.') {
+        let (name, index) = split_segment(segment);
+        let Some((key, next)) = item.as_table_like().and_then(|t| t.get_key_value(name)) else {
+            break;
+        };
+        span = key.span().or(next.span()).or(span);
+        item = next;
+
+        if let Some(index) = index {
+            let Some(next) = item.get(index) else {
+                break;
+            };
+            span = next.span().or(span);
+            item = next;
+        }
+    }
+
+    span.map(|span| super::line_of(source, span.start))
+}
+
+// item of a key path, None if any part of it is missing
+pub(super) fn get_mut<'a>(item: &'a mut Item, path: &str) -> Option<&'a mut Item> {
+    let mut item = item;
+    for segment in path.split('.') {
+        let (name, index) = split_segment(segment);
+        item = item.as_table_like_mut()?.get_mut(name)?;
+        if let Some(index) = index {
+            item = item.get_mut(index)?;
+        }
+    }
+    Some(item)
+}
+
+// replace the value of an existing key, its comments are kept
+pub(super) fn set(document: &mut DocumentMut, path: &str, value: Value) -> bool {
+    match get_mut(document.as_item_mut(), path).and_then(|item| item.as_value_mut()) {
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+            true
+        }
+        None => false,
+    }
+}
+
+// replace the value of an existing key, comments and layout of the file are kept
+pub(super) fn set_value(source: &str, path: &str, value: Value) -> Option<String> {
+    let mut document = source.parse::<DocumentMut>().ok()?;
+    set(&mut document, path, value).then(|| document.to_string())
+}
+
+// fill line numbers of errors from toml source
+pub fn locate(errors: &mut [ConfigError], source: &str) {
+    for e in errors.iter_mut() {
+        e.line = find_line(source, &e.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r##"version = "v2"
+brand = "test"
+model = "test"
+temperature_unit = "C"
+alarms = []
+
+[serial]
+port = "COM3"
+baud_rate = 9600
+data_bits = 8
+parity = "none"
+stop_bits = 1
+
+[serial.ta612c]
+
+[[serial.ta612c.channel]]
+channel_id = "BT"
+label = "BT"
+color = "#ff0000"
+
+[[serial.ta612c.channel]]
+channel_id = "ET"
+label = "ET"
+color = "#00ff00"
+"##;
+
+    fn validate(source: &str) -> Vec<ConfigError> {
+        let config = toml::from_str::<Config>(source).unwrap();
+        let mut errors = config.validate();
+        locate(&mut errors, source);
+        errors
+    }
+
+    #[test]
+    fn valid_config() {
+        let errors = validate(VALID);
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn duplicate_channel_id_fatal() {
+        let source = VALID.replace("channel_id = \"ET\"", "channel_id = \"BT\"");
+        let errors = validate(&source);
+
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].fatal);
+        assert_eq!(errors[0].path, "serial.ta612c.channel[1].channel_id");
+        assert_eq!(errors[0].line, Some(22));
+        assert!(errors[0].message.contains("duplicate channel_id \"BT\""));
+        assert!(crate::config::has_fatal(&errors));
+    }
+
+    #[test]
+    fn error_lines() {
+        let source = r#"version = "v2"
+serial = { port = "COM3", modbus = { slave = [{ id = 1 }, { id = 300 }] } }
+tcp.ip = "127.0.0.1"
+tcp."port" = 502
+
+[pid]
+output = "gas"
+
+[[manual_channel]]
+channel_id = "gas"
+
+[[manual_channel]]
+channel_id = "air"
+"#;
+        let cases = [
+            ("version", Some(1)),
+            ("serial.port", Some(2)),
+            ("serial.modbus.slave[1].id", Some(2)),
+            ("tcp.ip", Some(3)),
+            ("tcp.port", Some(4)),
+            ("pid.output", Some(7)),
+            // missing keys point at the closest table
+            ("pid.kp", Some(6)),
+            ("manual_channel[1].channel_id", Some(13)),
+            ("manual_channel[1].modbus.id", Some(12)),
+            ("nothing", None),
+        ];
+
+        for (path, line) in cases {
+            assert_eq!(find_line(source, path), line, "{}", path);
+        }
+    }
+}
@@ -1,13 +1,512 @@
-use std::io::Error;
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
-use serde_json::Value;
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+
+use crate::config::{Config, ModbusWrite};
+
+use crate::sample::{ChannelReading, Quality, Sample};
 
 pub mod http;
+pub mod merge;
 pub mod modbus;
+pub mod modbus_tcp;
+pub mod serial;
 pub mod ta612c;
 
 #[async_trait]
 pub trait Device {
-    async fn read(self: &mut Self) -> Result<Value, Error>;
+    async fn read(self: &mut Self) -> Result<Sample, DeviceError>;
+
+    // close the port or socket if still open, and open it again
+    async fn reconnect(self: &mut Self) -> Result<(), DeviceError>;
+
+    // write the value of a manual channel to target, returns the value read back
+    async fn write(
+        self: &mut Self,
+        _target: &ModbusWrite,
+        _value: f64,
+    ) -> Result<f64, DeviceError> {
+        Err(DeviceError::Config(
+            "writing is supported by modbus devices only".to_string(),
+        ))
+    }
+}
+
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+pub fn new_device(config: Config) -> Result<Box<dyn Device + Send>, DeviceError> {
+    let device: Box<dyn Device + Send>;
+
+    // serial has priority over tcp
+    match config.serial.clone() {
+        Some(serial) => match serial.modbus {
+            Some(_) => {
+                device = Box::new(modbus::ModbusDevice::new(config)?);
+            }
+            None => {
+                device = Box::new(ta612c::Ta612cDevice::new(config)?);
+            }
+        },
+        None => match config.tcp.as_ref().and_then(|tcp| tcp.modbus.as_ref()) {
+            Some(_) => {
+                device = Box::new(modbus_tcp::ModbusTcpDevice::new(config)?);
+            }
+            None => {
+                device = Box::new(http::HttpDevice::new(config)?);
+            }
+        },
+    }
+
+    Ok(device)
+}
+
+// reopen the device until it succeeds, waiting 1s, 2s, 4s ... 30s between attempts,
+// on_failure is called after every failed attempt
+pub async fn reconnect<F>(device: &mut Box<dyn Device + Send>, mut on_failure: F)
+where
+    F: FnMut(&DeviceError) + Send,
+{
+    let mut delay = RECONNECT_DELAY_MIN;
+
+    loop {
+        sleep(delay).await;
+
+        match device.reconnect().await {
+            Ok(_) => {
+                info!("device reconnected");
+                return;
+            }
+            Err(e) => {
+                warn!("device reconnect failed, retry in {:?} : {}", delay, e);
+                on_failure(&e);
+
+                delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+            }
+        }
+    }
+}
+
+// a value for Device::write, passed to the reader owning the device,
+// the result is sent back on reply
+pub struct WriteRequest {
+    pub target: ModbusWrite,
+    pub value: f64,
+    pub reply: oneshot::Sender<Result<f64, DeviceError>>,
+}
+
+// a device of Config::devices(), opened by new_device
+pub struct Reader {
+    pub name: String,
+    pub config: Config,
+    pub device: Box<dyn Device + Send>,
+    pub writes: UnboundedReceiver<WriteRequest>, // served between reads
+}
+
+impl Reader {
+    // the sender writes through the device while it is read, dropping it disables writing
+    pub fn new(
+        name: String,
+        config: Config,
+        device: Box<dyn Device + Send>,
+    ) -> (Reader, UnboundedSender<WriteRequest>) {
+        let (tx, writes) = unbounded_channel();
+        (
+            Reader {
+                name,
+                config,
+                device,
+                writes,
+            },
+            tx,
+        )
+    }
+}
+
+// read one device every sample interval of its config until the merger is gone, writes are
+// done between reads, the device is reopened when lost, on_status is called after every read
+// and reconnect attempt
+async fn read_loop<F>(
+    reader: Reader,
+    index: usize,
+    tx: UnboundedSender<(usize, Sample, Instant)>,
+    on_status: F,
+) where
+    F: Fn(&DeviceStatus) + Send + Sync,
+{
+    let Reader {
+        name,
+        config,
+        mut device,
+        mut writes,
+    } = reader;
+    let sample_interval = config.sample_interval();
+
+    let mut interval = interval(sample_interval);
+    // a slow read delays the next tick instead of firing missed ticks in a burst
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut status = DeviceStatus::connected(&name);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tx.closed() => return,
+            Some(request) = writes.recv() => {
+                let result = device.write(&request.target, request.value).await;
+                if let Err(e) = &result {
+                    error!("device {} write failed ({}) : {}", name, e.kind(), e);
+                }
+                // a lost connection is noticed and reopened by the next read
+                let _ = request.reply.send(result);
+                continue;
+            }
+        }
+
+        match device.read().await {
+            Ok(mut sample) => {
+                let read_at = Instant::now();
+                sample.timestamp = unix_millis();
+                if tx.send((index, sample, read_at)).is_err() {
+                    return;
+                }
+                status.read_succeeded();
+            }
+            Err(e) => {
+                error!("device {} read failed ({}) : {}", name, e.kind(), e);
+                status.read_failed(&e);
+
+                if e.is_connection_lost() || status.state == ConnectionState::Disconnected {
+                    status.disconnected();
+                    on_status(&status);
+
+                    let reconnected = reconnect(&mut device, |e| {
+                        status.read_failed(e);
+                        status.disconnected();
+                        on_status(&status);
+                    });
+                    tokio::select! {
+                        _ = reconnected => {}
+                        _ = tx.closed() => return,
+                    }
+                    status.reconnected();
+                    interval.reset();
+                }
+            }
+        }
+
+        on_status(&status);
+    }
+}
+
+// read all devices concurrently and merge their samples by channel_id, a merged sample is
+// passed to on_sample as soon as every device delivered, or at the latest every sample_interval,
+// together with when its newest reading was read. device i/o blocks, so every reader runs on its
+// own thread, a slow device does not hold up the others, the merging or the tauri commands.
+// readers stop after their current read when this future is dropped or on_sample fails
+pub async fn acquire<S, T>(
+    readers: Vec<Reader>,
+    channel_ids: Vec<String>,
+    sample_interval: Duration,
+    mut on_sample: S,
+    on_status: T,
+) -> Result<(), String>
+where
+    S: FnMut(Sample, Instant) -> Result<(), String>,
+    T: Fn(&DeviceStatus) + Send + Sync + 'static,
+{
+    // a reading is held until its device had one more interval and read timeout to deliver again
+    let max_age = readers
+        .iter()
+        .map(|r| r.config.sample_interval() + r.config.read_timeout())
+        .collect();
+    let mut merger = merge::Merger::new(channel_ids, max_age);
+
+    let (tx, mut rx) = unbounded_channel();
+    let on_status = Arc::new(on_status);
+
+    for (index, reader) in readers.into_iter().enumerate() {
+        let tx = tx.clone();
+        let on_status = on_status.clone();
+        let name = reader.name.clone();
+        thread::Builder::new()
+            .name(format!("reader {}", name))
+            .spawn(move || {
+                let runtime = match runtime::Builder::new_current_thread().enable_all().build() {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        error!("device {} reader runtime failed : {}", reader.name, e);
+                        return;
+                    }
+                };
+                runtime.block_on(read_loop(reader, index, tx, move |status| {
+                    on_status(status)
+                }));
+            })
+            .map_err(|e| format!("failed to start reader of device {} : {}", name, e))?;
+    }
+    drop(tx);
+
+    let mut interval = interval(sample_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Some((index, sample, read_at)) => {
+                    merger.update(index, sample, read_at);
+                    if !merger.is_complete() {
+                        continue;
+                    }
+                    interval.reset();
+                }
+                None => return Err("all device readers stopped".to_string()),
+            },
+            _ = interval.tick() => {}
+        }
+
+        if let Some((sample, read_at)) = merger.merge() {
+            on_sample(sample, read_at)?;
+        }
+    }
+}
+
+// a sample without any good channel is a failed read
+pub fn sample_or_error(
+    channels: Vec<ChannelReading>,
+    error: DeviceError,
+) -> Result<Sample, DeviceError> {
+    if channels.iter().any(|c| c.quality == Quality::Good) {
+        Ok(Sample::new(channels))
+    } else {
+        Err(error)
+    }
+}
+
+// consecutive failures before a degraded device is reported as disconnected
+const DISCONNECTED_THRESHOLD: u32 = 5;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connected,    // device opened, no read yet
+    Reading,      // last read succeeded
+    Degraded,     // last read failed
+    Disconnected, // device closed, or too many consecutive failures
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceStatus {
+    pub device: String, // name of Config::devices()
+    pub state: ConnectionState,
+    pub last_error: Option<String>,
+    pub last_error_kind: Option<String>,
+    pub consecutive_failures: u32,
+    pub last_success: Option<u64>, // unix timestamp in milliseconds
+}
+
+impl DeviceStatus {
+    pub fn new(device: &str) -> Self {
+        Self {
+            device: device.to_string(),
+            state: ConnectionState::Disconnected,
+            last_error: None,
+            last_error_kind: None,
+            consecutive_failures: 0,
+            last_success: None,
+        }
+    }
+
+    pub fn connected(device: &str) -> Self {
+        Self {
+            state: ConnectionState::Connected,
+            ..Self::new(device)
+        }
+    }
+
+    pub fn read_succeeded(&mut self) {
+        self.state = ConnectionState::Reading;
+        self.consecutive_failures = 0;
+        self.last_success = Some(unix_millis());
+    }
+
+    pub fn read_failed(&mut self, e: &DeviceError) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(e.to_string());
+        self.last_error_kind = Some(e.kind().to_string());
+        if self.consecutive_failures >= DISCONNECTED_THRESHOLD {
+            self.state = ConnectionState::Disconnected;
+        } else {
+            self.state = ConnectionState::Degraded;
+        }
+    }
+
+    pub fn disconnected(&mut self) {
+        self.state = ConnectionState::Disconnected;
+    }
+
+    // keep failure count and last error until the next successful read
+    pub fn reconnected(&mut self) {
+        self.state = ConnectionState::Connected;
+    }
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub enum DeviceError {
+    Config(String),
+    PortOpen { port: String, message: String },
+    NotConnected,
+    Io(std::io::Error),
+    Timeout(String),
+    Checksum,
+    ModbusException(u8),
+    MalformedFrame(String),
+    Http(String),
+    HttpStatus(u16),
+    JsonDecode(String),
+    ReadBack(String),
+}
+
+impl DeviceError {
+    // short, stable name of the error kind, for logs and frontend
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeviceError::Config(_) => "config",
+            DeviceError::PortOpen { .. } => "port_open",
+            DeviceError::NotConnected => "not_connected",
+            DeviceError::Io(_) => "io",
+            DeviceError::Timeout(_) => "timeout",
+            DeviceError::Checksum => "checksum",
+            DeviceError::ModbusException(_) => "modbus_exception",
+            DeviceError::MalformedFrame(_) => "malformed_frame",
+            DeviceError::Http(_) => "http",
+            DeviceError::HttpStatus(_) => "http_status",
+            DeviceError::JsonDecode(_) => "json_decode",
+            DeviceError::ReadBack(_) => "read_back",
+        }
+    }
+
+    // the port or socket is unusable and has to be reopened
+    pub fn is_connection_lost(&self) -> bool {
+        matches!(
+            self,
+            DeviceError::PortOpen { .. }
+                | DeviceError::NotConnected
+                | DeviceError::Io(_)
+                | DeviceError::Http(_)
+        )
+    }
+}
+
+fn modbus_exception_name(code: u8) -> &'static str {
+    match code {
+        1 => "illegal function",
+        2 => "illegal data address",
+        3 => "illegal data value",
+        4 => "slave device failure",
+        5 => "acknowledge",
+        6 => "slave device busy",
+        7 => "negative acknowledge",
+        8 => "memory parity error",
+        10 => "gateway path unavailable",
+        11 => "gateway target device failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::Config(msg) => write!(f, "config error: {}", msg),
+            DeviceError::PortOpen { port, message } => {
+                write!(f, "failed to open {}: {}", port, message)
+            }
+            DeviceError::NotConnected => write!(f, "device is not connected"),
+            DeviceError::Io(e) => write!(f, "io error: {}", e),
+            DeviceError::Timeout(msg) => write!(f, "timeout: {}", msg),
+            DeviceError::Checksum => write!(f, "CRC/LRC mismatch in response frame"),
+            DeviceError::ModbusException(code) => write!(
+                f,
+                "modbus exception {:#04x} ({})",
+                code,
+                modbus_exception_name(*code)
+            ),
+            DeviceError::MalformedFrame(msg) => write!(f, "malformed frame: {}", msg),
+            DeviceError::Http(msg) => write!(f, "http error: {}", msg),
+            DeviceError::HttpStatus(status) => write!(f, "http status {}", status),
+            DeviceError::JsonDecode(msg) => write!(f, "json decode error: {}", msg),
+            DeviceError::ReadBack(msg) => write!(f, "read back mismatch: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+impl From<std::io::Error> for DeviceError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+                DeviceError::Timeout(e.to_string())
+            }
+            _ => DeviceError::Io(e),
+        }
+    }
+}
+
+impl From<rmodbus::ErrorKind> for DeviceError {
+    fn from(e: rmodbus::ErrorKind) -> Self {
+        use rmodbus::ErrorKind;
+
+        match e {
+            ErrorKind::FrameCRCError => DeviceError::Checksum,
+            ErrorKind::IllegalFunction => DeviceError::ModbusException(1),
+            ErrorKind::IllegalDataAddress => DeviceError::ModbusException(2),
+            ErrorKind::IllegalDataValue => DeviceError::ModbusException(3),
+            ErrorKind::SlaveDeviceFailure => DeviceError::ModbusException(4),
+            ErrorKind::Acknowledge => DeviceError::ModbusException(5),
+            ErrorKind::SlaveDeviceBusy => DeviceError::ModbusException(6),
+            ErrorKind::NegativeAcknowledge => DeviceError::ModbusException(7),
+            ErrorKind::MemoryParityError => DeviceError::ModbusException(8),
+            ErrorKind::GatewayPathUnavailable => DeviceError::ModbusException(10),
+            ErrorKind::GatewayTargetFailed => DeviceError::ModbusException(11),
+            other => DeviceError::MalformedFrame(format!("{:?}", other)),
+        }
+    }
+}
+
+impl From<reqwest::Error> for DeviceError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            DeviceError::Timeout(e.to_string())
+        } else if e.is_decode() {
+            DeviceError::JsonDecode(e.to_string())
+        } else if let Some(status) = e.status() {
+            DeviceError::HttpStatus(status.as_u16())
+        } else {
+            DeviceError::Http(e.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for DeviceError {
+    fn from(e: serde_json::Error) -> Self {
+        DeviceError::JsonDecode(e.to_string())
+    }
 }
@@ -1,13 +1,13 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use async_trait::async_trait;
-use log::error;
+use log::{error, warn};
 use serde_json::Value;
-use std::io::{Error, ErrorKind};
-use tokio::time;
+use std::time::Instant;
 
-use super::Device;
+use super::{sample_or_error, Device, DeviceError};
 use crate::config::Config;
+use crate::sample::{ChannelReading, Sample};
 
 pub struct HttpDevice {
     config: Config,
@@ -15,41 +15,107 @@ pub struct HttpDevice {
 }
 
 impl HttpDevice {
-    pub fn new(config: Config) -> HttpDevice {
-        let client = reqwest::Client::new();
+    pub fn new(config: Config) -> Result<HttpDevice, DeviceError> {
+        let tcp = config
+            .tcp
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [tcp]".to_string()))?;
 
-        HttpDevice { config, client }
+        let client = reqwest::Client::builder()
+            .connect_timeout(tcp.timeout())
+            .build()?;
+
+        Ok(HttpDevice { config, client })
     }
 }
 
 #[async_trait]
 impl Device for HttpDevice {
-    async fn read(self: &mut Self) -> Result<Value, Error> {
-        let mut res_json: Value = Value::Null;
+    async fn read(self: &mut Self) -> Result<Sample, DeviceError> {
+        let start = Instant::now();
 
-        // 10 seconds timeout
-        let res = tokio::time::timeout(time::Duration::from_secs(10), async {
+        let res = tokio::time::timeout(self.config.read_timeout(), async {
             // read channels
             let config = &self.config;
-            let tcp = config.tcp.as_ref().unwrap();
+            let tcp = config
+                .tcp
+                .as_ref()
+                .ok_or(DeviceError::Config("missing [tcp]".to_string()))?;
 
             let url = format!("http://{}:{}", tcp.ip, tcp.port);
             // info!("url : {}", url);
 
-            let req = self.client.get(url);
-            let res_str = req.send().await.unwrap().text().await.unwrap();
-            res_json = serde_json::from_str(&res_str).unwrap();
+            let res = self.client.get(url).send().await?;
+            if !res.status().is_success() {
+                return Err(DeviceError::HttpStatus(res.status().as_u16()));
+            }
+
+            let res_str = res.text().await?;
+            let res_json: Value = serde_json::from_str(&res_str)?;
+
+            Ok(res_json)
         });
 
-        match res.await {
-            Ok(_) => (),
+        let res_json = match res.await {
+            Ok(result) => result?,
             Err(_) => {
                 error!("http request timeout");
-                return Err(Error::new(ErrorKind::Other, "http request timeout"));
+                return Err(DeviceError::Timeout("http request timeout".to_string()));
+            }
+        };
+
+        // all channels come in one response, they share the request latency
+        let latency_ms = start.elapsed().as_millis() as u64;
+
+        let http = self
+            .config
+            .tcp
+            .as_ref()
+            .and_then(|tcp| tcp.http.as_ref())
+            .ok_or(DeviceError::Config("missing [tcp.http]".to_string()))?;
+
+        let mut channels = Vec::new();
+        for c in &http.channel {
+            let unit = self.config.channel_unit(&c.unit);
+
+            // numbers may be sent as strings
+            let value = match res_json.get(&c.channel_id) {
+                Some(Value::String(s)) => s.trim().parse::<f64>().ok(),
+                Some(v) => v.as_f64(),
+                None => None,
+            };
+
+            match value {
+                Some(v) => channels.push(ChannelReading::good(
+                    c.channel_id.clone(),
+                    v,
+                    unit,
+                    latency_ms,
+                )),
+                None => {
+                    warn!("channel {} missing or not a number in http response", c.channel_id);
+                    channels.push(ChannelReading::bad(c.channel_id.clone(), unit, latency_ms));
+                }
             }
         }
 
-        // Ok(Value::Object(map))
-        Ok(res_json)
+        sample_or_error(
+            channels,
+            DeviceError::JsonDecode("no channel found in http response".to_string()),
+        )
+    }
+
+    async fn reconnect(self: &mut Self) -> Result<(), DeviceError> {
+        let tcp = self
+            .config
+            .tcp
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [tcp]".to_string()))?;
+
+        // reqwest opens connections per request, a new client drops the pooled ones
+        self.client = reqwest::Client::builder()
+            .connect_timeout(tcp.timeout())
+            .build()?;
+        Ok(())
     }
 }
@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// align samples of several devices into one sample stream keyed by channel_id,
+// every device is read on its own thread with its own interval, see devices::acquire
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::sample::{ChannelReading, Quality, Sample};
+
+pub struct Merger {
+    channel_ids: Vec<String>, // order of channels in merged samples
+    max_age: Vec<Duration>,   // per device, older readings are bad
+    latest: HashMap<String, (usize, ChannelReading, Instant)>, // channel_id -> (device, reading, read)
+    fresh: Vec<bool>,               // per device, delivered since the last merge
+    newest: Option<(u64, Instant)>, // timestamp and time of the newest read since the last merge
+}
+
+impl Merger {
+    pub fn new(channel_ids: Vec<String>, max_age: Vec<Duration>) -> Self {
+        let fresh = vec![false; max_age.len()];
+        Self {
+            channel_ids,
+            max_age,
+            latest: HashMap::new(),
+            fresh,
+            newest: None,
+        }
+    }
+
+    // read_at is when Device::read returned, sample.timestamp is set by the reader at the same time
+    pub fn update(&mut self, device: usize, sample: Sample, read_at: Instant) {
+        match self.newest {
+            Some((_, newest)) if newest >= read_at => {}
+            _ => self.newest = Some((sample.timestamp, read_at)),
+        }
+        for reading in sample.channels {
+            self.latest
+                .insert(reading.channel_id.clone(), (device, reading, read_at));
+        }
+        if let Some(fresh) = self.fresh.get_mut(device) {
+            *fresh = true;
+        }
+    }
+
+    // every device delivered since the last merge
+    pub fn is_complete(&self) -> bool {
+        self.fresh.iter().all(|f| *f)
+    }
+
+    // latest reading of every channel, readings of slower devices are repeated until they
+    // are older than max_age, None if no device delivered since the last merge or no channel is good,
+    // the sample has the timestamp of the newest read merged and is returned with its read time
+    pub fn merge(&mut self) -> Option<(Sample, Instant)> {
+        if !self.fresh.iter().any(|f| *f) {
+            return None;
+        }
+        self.fresh.iter_mut().for_each(|f| *f = false);
+        let (timestamp, read_at) = self.newest.take()?;
+
+        let now = Instant::now();
+        let channels: Vec<ChannelReading> = self
+            .channel_ids
+            .iter()
+            .filter_map(|id| self.latest.get(id))
+            .map(|(device, reading, received)| {
+                if now.duration_since(*received) > self.max_age[*device] {
+                    ChannelReading::bad(
+                        reading.channel_id.clone(),
+                        reading.unit.clone(),
+                        reading.latency_ms,
+                    )
+                } else {
+                    reading.clone()
+                }
+            })
+            .collect();
+
+        if !channels.iter().any(|c| c.quality == Quality::Good) {
+            return None;
+        }
+
+        let mut sample = Sample::new(channels);
+        sample.timestamp = timestamp;
+        Some((sample, read_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64, channels: &[&str]) -> Sample {
+        let mut sample = Sample::new(
+            channels
+                .iter()
+                .map(|id| ChannelReading::good(id.to_string(), 100.0, "C".to_string(), 0))
+                .collect(),
+        );
+        sample.timestamp = timestamp;
+        sample
+    }
+
+    fn ago(ms: u64) -> Instant {
+        Instant::now()
+            .checked_sub(Duration::from_millis(ms))
+            .unwrap()
+    }
+
+    fn qualities(sample: &Sample) -> Vec<(&str, Quality)> {
+        sample
+            .channels
+            .iter()
+            .map(|c| (c.channel_id.as_str(), c.quality))
+            .collect()
+    }
+
+    fn merger() -> Merger {
+        Merger::new(
+            vec!["BT".to_string(), "ET".to_string(), "inlet".to_string()],
+            vec![Duration::from_secs(2), Duration::from_secs(2)],
+        )
+    }
+
+    #[test]
+    fn complete_when_every_device_delivered() {
+        let mut merger = merger();
+        assert!(merger.merge().is_none());
+
+        merger.update(0, sample(1000, &["BT", "ET"]), ago(0));
+        assert!(!merger.is_complete());
+        merger.update(1, sample(1100, &["inlet"]), ago(0));
+        assert!(merger.is_complete());
+
+        let (merged, _) = merger.merge().unwrap();
+        assert_eq!(
+            qualities(&merged),
+            vec![
+                ("BT", Quality::Good),
+                ("ET", Quality::Good),
+                ("inlet", Quality::Good)
+            ]
+        );
+        assert!(!merger.is_complete());
+
+        // nothing new since the last merge
+        assert!(merger.merge().is_none());
+    }
+
+    #[test]
+    fn slower_device_repeated_until_max_age() {
+        let mut merger = merger();
+
+        // inlet is repeated while younger than max_age
+        merger.update(1, sample(1000, &["inlet"]), ago(1000));
+        merger.update(0, sample(2000, &["BT", "ET"]), ago(0));
+        let (merged, _) = merger.merge().unwrap();
+        assert_eq!(merged.channels.len(), 3);
+        assert!(merged.channels.iter().all(|c| c.quality == Quality::Good));
+
+        // and bad once older
+        merger.update(1, sample(1000, &["inlet"]), ago(3000));
+        merger.update(0, sample(3000, &["BT", "ET"]), ago(0));
+        let (merged, _) = merger.merge().unwrap();
+        assert_eq!(
+            qualities(&merged),
+            vec![
+                ("BT", Quality::Good),
+                ("ET", Quality::Good),
+                ("inlet", Quality::Bad)
+            ]
+        );
+        assert_eq!(merged.channels[2].value, None);
+    }
+
+    #[test]
+    fn only_stale_channels_merge_to_none() {
+        let mut merger = merger();
+        merger.update(0, sample(1000, &["BT", "ET"]), ago(3000));
+        assert!(merger.merge().is_none());
+    }
+
+    #[test]
+    fn timestamp_of_newest_read() {
+        let mut merger = merger();
+        let newest = ago(100);
+        merger.update(0, sample(2000, &["BT", "ET"]), newest);
+        // a read delivered late does not move the timestamp back
+        merger.update(1, sample(1000, &["inlet"]), ago(500));
+
+        let (merged, read_at) = merger.merge().unwrap();
+        assert_eq!(merged.timestamp, 2000);
+        assert_eq!(read_at, newest);
+    }
+}
@@ -1,178 +1,934 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use async_trait::async_trait;
-use log::error;
+use log::{info, warn};
 use rmodbus::{
     client::ModbusRequest, generate_ascii_frame, guess_response_frame_len, parse_ascii_frame,
     ModbusProto,
 };
-use serde_json::{to_value, Map, Value};
-use serialport::{DataBits, Parity, SerialPort, StopBits};
-use std::{
-    io::{Error, ErrorKind},
-    time::Duration,
-};
-use tokio::time;
+use serialport::{ClearBuffer, SerialPort};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
 
-use super::Device;
-use crate::config::{Config, Slave};
+use super::serial::open;
+use super::{sample_or_error, Device, DeviceError};
+use crate::config::{ByteOrder, Config, DecodeType, Modbus, ModbusProtocol, ModbusWrite, Slave};
+use crate::sample::{ChannelReading, Sample};
 
 pub struct ModbusDevice {
-    stream: Box<dyn SerialPort>,
+    stream: Option<Box<dyn SerialPort>>,
     config: Config,
+    blocks: Vec<Block>, // requests of one read, see plan()
 }
 
 impl ModbusDevice {
-    pub fn new(config: Config) -> ModbusDevice {
-        let serial = config.serial.as_ref().unwrap();
+    pub fn new(config: Config) -> Result<ModbusDevice, DeviceError> {
+        let serial = config
+            .serial
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial]".to_string()))?;
+
+        let blocks = serial.modbus.as_ref().map(plan).unwrap_or_default();
+
+        let stream = open(serial)?;
+
+        Ok(ModbusDevice {
+            stream: Some(stream),
+            config,
+            blocks,
+        })
+    }
+}
+
+// number of 16 bit registers needed by decode_type
+pub fn register_count(slave: &Slave) -> u16 {
+    slave.decode_type.register_count()
+}
+
+// registers of one unit id and function, read by one request
+pub struct Block {
+    pub id: u16,
+    pub function: u16,
+    pub start: u16,
+    pub count: u16,
+    pub slaves: Vec<usize>, // indexes in modbus.slave of the channels decoded from this block
+}
+
+// group channels by unit id and function into contiguous register ranges, with at most
+// max_gap unused registers between channels and max_block registers per request,
+// blocks are in the order of their first channel
+pub fn plan(modbus: &Modbus) -> Vec<Block> {
+    let max_gap = modbus.max_gap() as u32;
+    let max_block = modbus.max_block() as u32;
 
-        let timeout = Duration::from_secs(1);
+    let mut order: Vec<usize> = (0..modbus.slave.len()).collect();
+    order.sort_by_key(|i| {
+        let slave = &modbus.slave[*i];
+        (slave.id, slave.function, slave.registry)
+    });
 
-        let mut data_bits = DataBits::Eight;
-        if serial.data_bits == 7 {
-            data_bits = DataBits::Seven;
-        } else if serial.data_bits == 6 {
-            data_bits = DataBits::Six;
-        } else if serial.data_bits == 5 {
-            data_bits = DataBits::Five;
+    let mut blocks: Vec<Block> = Vec::new();
+    for i in order {
+        let slave = &modbus.slave[i];
+        let start = slave.registry as u32;
+        let end = start + register_count(slave) as u32;
+
+        match blocks.last_mut() {
+            Some(block)
+                if block.id == slave.id
+                    && block.function == slave.function
+                    && start <= block.start as u32 + block.count as u32 + max_gap
+                    && end.max(block.start as u32 + block.count as u32) - block.start as u32
+                        <= max_block =>
+            {
+                block.count =
+                    (end.max(block.start as u32 + block.count as u32) - block.start as u32) as u16;
+                block.slaves.push(i);
+            }
+            _ => blocks.push(Block {
+                id: slave.id,
+                function: slave.function,
+                start: slave.registry,
+                count: register_count(slave),
+                slaves: vec![i],
+            }),
         }
+    }
+
+    blocks.sort_by_key(|block| block.slaves.iter().min().copied());
+
+    info!(
+        "modbus {} channel(s) in {} request(s)",
+        modbus.slave.len(),
+        blocks.len()
+    );
+    blocks
+}
+
+// function 3 : read holding registers
+// function 4 : read input registers
+pub fn generate_request(
+    mreq: &mut ModbusRequest,
+    block: &Block,
+    request: &mut Vec<u8>,
+) -> Result<(), DeviceError> {
+    if block.function == 4 {
+        mreq.generate_get_inputs(block.start, block.count, request)?;
+    } else {
+        mreq.generate_get_holdings(block.start, block.count, request)?;
+    }
+    Ok(())
+}
+
+// channels of a block decoded from its registers, all bad when the request failed
+pub fn block_readings(
+    config: &Config,
+    slaves: &[Slave],
+    block: &Block,
+    data: Option<&[u16]>,
+    latency_ms: u64,
+    readings: &mut [Option<ChannelReading>],
+) {
+    for i in &block.slaves {
+        let slave = &slaves[*i];
+        let unit = config.channel_unit(&slave.unit);
+        let offset = (slave.registry - block.start) as usize;
 
-        let mut parity = Parity::None;
-        let parity_lowercase = serial.parity.to_lowercase();
-        if parity_lowercase == "even" {
-            parity = Parity::Even;
-        } else if parity_lowercase == "odd" {
-            parity = Parity::Odd;
+        let value = match data {
+            Some(data) => match decode(slave, data.get(offset..).unwrap_or_default()) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    warn!("channel {} decode failed : {}", slave.channel_id, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        readings[*i] = Some(match value {
+            Some(value) => ChannelReading::good(slave.channel_id.clone(), value, unit, latency_ms),
+            None => ChannelReading::bad(slave.channel_id.clone(), unit, latency_ms),
+        });
+    }
+}
+
+// decode registers according to decode_type, byte_order, word_order and divisor
+pub fn decode(slave: &Slave, data: &[u16]) -> Result<f64, DeviceError> {
+    decode_words(
+        slave.decode_type,
+        &slave.byte_order,
+        &slave.word_order,
+        slave.divisor.max(1),
+        data,
+    )
+}
+
+fn little_endian(order: &Option<ByteOrder>) -> bool {
+    *order == Some(ByteOrder::Little)
+}
+
+fn decode_words(
+    decode_type: DecodeType,
+    byte_order: &Option<ByteOrder>,
+    word_order: &Option<ByteOrder>,
+    divisor: u16,
+    data: &[u16],
+) -> Result<f64, DeviceError> {
+    if data.len() < decode_type.register_count() as usize {
+        return Err(DeviceError::MalformedFrame(format!(
+            "expected {} registers, got {}",
+            decode_type.register_count(),
+            data.len()
+        )));
+    }
+
+    let words: Vec<u16> = data
+        .iter()
+        .map(|w| {
+            if little_endian(byte_order) {
+                w.swap_bytes()
+            } else {
+                *w
+            }
+        })
+        .collect();
+
+    let raw32 = || -> u32 {
+        let (high, low) = if little_endian(word_order) {
+            (words[1], words[0])
+        } else {
+            (words[0], words[1])
+        };
+        ((high as u32) << 16) | low as u32
+    };
+
+    let value = match decode_type {
+        DecodeType::U16 => words[0] as f64,
+        DecodeType::I16 => words[0] as i16 as f64,
+        DecodeType::U32 => raw32() as f64,
+        DecodeType::I32 => raw32() as i32 as f64,
+        DecodeType::F32 => f32::from_bits(raw32()) as f64,
+    };
+
+    Ok(value / divisor as f64)
+}
+
+// registers of a value written to target, the reverse of decode,
+// integers are rounded and saturated to the range of decode_type
+pub fn encode(target: &ModbusWrite, value: f64) -> Vec<u16> {
+    let scaled = value * target.divisor() as f64;
+
+    let raw32 = |raw: u32| -> Vec<u16> {
+        let (high, low) = ((raw >> 16) as u16, raw as u16);
+        if little_endian(&target.word_order) {
+            vec![low, high]
+        } else {
+            vec![high, low]
         }
+    };
+
+    let words = match target.decode_type() {
+        DecodeType::U16 => vec![scaled.round() as u16],
+        DecodeType::I16 => vec![scaled.round() as i16 as u16],
+        DecodeType::U32 => raw32(scaled.round() as u32),
+        DecodeType::I32 => raw32(scaled.round() as i32 as u32),
+        DecodeType::F32 => raw32((scaled as f32).to_bits()),
+    };
 
-        let mut stop_bits = StopBits::One;
-        if serial.stop_bits == 2 {
-            stop_bits = StopBits::Two;
+    words
+        .into_iter()
+        .map(|w| {
+            if little_endian(&target.byte_order) {
+                w.swap_bytes()
+            } else {
+                w
+            }
+        })
+        .collect()
+}
+
+// read the registers of a block over any transport, transact sends a request frame
+// and returns the response frame, binary frames on both sides
+pub fn read_block<T>(
+    block: &Block,
+    proto: ModbusProto,
+    mut transact: T,
+) -> Result<Vec<u16>, DeviceError>
+where
+    T: FnMut(&[u8]) -> Result<Vec<u8>, DeviceError>,
+{
+    // create request object
+    let mut mreq = ModbusRequest::new(block.id as u8, proto);
+    let mut request = Vec::new();
+
+    generate_request(&mut mreq, block, &mut request)?;
+
+    let response = transact(&request)?;
+
+    let mut data = Vec::new();
+
+    // check if frame has no Modbus error inside and parse response into data vec
+    mreq.parse_u16(&response, &mut data)?;
+
+    Ok(data)
+}
+
+// read all blocks of a device, read sends the request of one block and returns its registers,
+// a failed block only makes its channels bad unless is_fatal, then the whole read fails.
+// the i/o is blocking, read gets what is left of read_timeout to cut its transport timeout to,
+// so a read lasts at most read_timeout plus a few ms
+pub fn read_blocks<T, F>(
+    config: &Config,
+    slaves: &[Slave],
+    blocks: &[Block],
+    mut read: T,
+    is_fatal: F,
+) -> Result<Sample, DeviceError>
+where
+    T: FnMut(&Block, Duration) -> Result<Vec<u16>, DeviceError>,
+    F: Fn(&DeviceError) -> bool,
+{
+    let mut readings: Vec<Option<ChannelReading>> = vec![None; slaves.len()];
+    let mut last_error = DeviceError::NotConnected;
+
+    let read_timeout = config.read_timeout();
+    let deadline = Instant::now() + read_timeout;
+
+    for block in blocks {
+        let start = Instant::now();
+        let remaining = deadline.saturating_duration_since(start);
+        if remaining.is_zero() {
+            return Err(DeviceError::Timeout(format!(
+                "read_timeout_ms {} exceeded",
+                read_timeout.as_millis()
+            )));
         }
 
-        let stream = serialport::new(&serial.port, serial.baud_rate as u32)
-            .data_bits(data_bits)
-            .parity(parity)
-            .stop_bits(stop_bits)
-            .timeout(timeout)
-            .open()
-            .expect("Failed to open port");
+        let result = read(block, remaining);
+        let latency_ms = start.elapsed().as_millis() as u64;
 
-        ModbusDevice { stream, config }
+        match result {
+            Ok(data) => block_readings(
+                config,
+                slaves,
+                block,
+                Some(&data),
+                latency_ms,
+                &mut readings,
+            ),
+            Err(e) if is_fatal(&e) => return Err(e),
+            Err(e) => {
+                // one request failing does not drop the others
+                warn!(
+                    "unit {} registers {} ~ {} read failed : {}",
+                    block.id,
+                    block.start,
+                    block.start as u32 + block.count as u32 - 1,
+                    e
+                );
+                block_readings(config, slaves, block, None, latency_ms, &mut readings);
+                last_error = e;
+            }
+        }
     }
+
+    sample_or_error(readings.into_iter().flatten().collect(), last_error)
 }
 
-async fn ascii(slave: &Slave, stream: &mut Box<dyn SerialPort>) -> f64 {
-    // create request object
-    let mut mreq = ModbusRequest::new(slave.id as u8, ModbusProto::Ascii);
+// function 6 : write single register
+// function 16 : write multiple registers
+// the registers are read back with function 3, returns the value read back
+pub fn write_value<T>(
+    target: &ModbusWrite,
+    value: f64,
+    proto: ModbusProto,
+    mut transact: T,
+) -> Result<f64, DeviceError>
+where
+    T: FnMut(&[u8]) -> Result<Vec<u8>, DeviceError>,
+{
+    let words = encode(target, value);
+
+    let mut mreq = ModbusRequest::new(target.id as u8, proto);
     let mut request = Vec::new();
+    if target.function == 6 {
+        mreq.generate_set_holding(target.registry, words[0], &mut request)?;
+    } else {
+        mreq.generate_set_holdings_bulk(target.registry, &words, &mut request)?;
+    }
 
-    // get holding registers
-    mreq.generate_get_holdings(slave.registry, 1, &mut request)
-        .unwrap();
+    let response = transact(&request)?;
+    mreq.parse_ok(&response)?;
+
+    let block = Block {
+        id: target.id,
+        function: 3,
+        start: target.registry,
+        count: words.len() as u16,
+        slaves: Vec::new(),
+    };
+    let data = read_block(&block, proto, transact)?;
+    if data != words {
+        return Err(DeviceError::ReadBack(format!(
+            "wrote {:?} to unit {} register {}, read back {:?}",
+            words, target.id, target.registry, data
+        )));
+    }
 
+    decode_words(
+        target.decode_type(),
+        &target.byte_order,
+        &target.word_order,
+        target.divisor(),
+        &data,
+    )
+}
+
+fn ascii(request: &[u8], stream: &mut Box<dyn SerialPort>) -> Result<Vec<u8>, DeviceError> {
     let mut request_ascii = Vec::new();
-    generate_ascii_frame(&request, &mut request_ascii).unwrap();
-    stream.write(&request_ascii).unwrap();
+    generate_ascii_frame(request, &mut request_ascii)?;
+    stream.write_all(&request_ascii)?;
 
     let mut buf = [0u8; 7];
-    stream.read_exact(&mut buf).unwrap();
+    stream.read_exact(&mut buf)?;
     let mut response_ascii = Vec::new();
     response_ascii.extend_from_slice(&buf);
-    let len = guess_response_frame_len(&buf, ModbusProto::Ascii).unwrap();
+    let len = guess_response_frame_len(&buf, ModbusProto::Ascii)?;
     if len > 7 {
         let mut rest = vec![0u8; (len - 7) as usize];
-        stream.read_exact(&mut rest).unwrap();
+        stream.read_exact(&mut rest)?;
         response_ascii.extend(rest);
     }
 
+    if len < 3 {
+        return Err(DeviceError::MalformedFrame(format!(
+            "ascii frame too short : {} bytes",
+            len
+        )));
+    }
+
     let mut response = vec![0; (len as usize - 3) / 2];
-    parse_ascii_frame(&response_ascii, len as usize, &mut response, 0).unwrap();
+    parse_ascii_frame(&response_ascii, len as usize, &mut response, 0)?;
     // println!("response {:02X?}", response);
-    let mut data = Vec::new();
 
-    mreq.parse_u16(&response, &mut data).unwrap();
-
-    let rounded_number = (data[0] as f64 * 10.0).round() / 100.0;
-
-    rounded_number
+    Ok(response)
 }
 
-async fn rtu(slave: &Slave, stream: &mut Box<dyn SerialPort>) -> f64 {
-    // create request object
-    let mut mreq = ModbusRequest::new(slave.id as u8, ModbusProto::Rtu);
-    let mut request = Vec::new();
-
-    // get holding registers
-    mreq.generate_get_holdings(slave.registry, 1, &mut request)
-        .unwrap();
-
-    stream.write(&request).unwrap();
+// also used for rtu frames over tcp, see modbus_tcp.rs
+pub fn rtu<S: Read + Write>(request: &[u8], stream: &mut S) -> Result<Vec<u8>, DeviceError> {
+    stream.write_all(request)?;
 
-    let mut buf = [0u8; 7];
-    stream.read_exact(&mut buf).unwrap();
+    // unit id, function and byte count are enough to guess the frame length,
+    // an exception response is only 5 bytes long
+    let mut buf = [0u8; 3];
+    stream.read_exact(&mut buf)?;
     let mut response = Vec::new();
     response.extend_from_slice(&buf);
-    let len = guess_response_frame_len(&buf, ModbusProto::Rtu).unwrap();
+    let len = guess_response_frame_len(&buf, ModbusProto::Rtu)?;
 
-    if len > 7 {
-        let mut rest = vec![0u8; (len - 7) as usize];
-        stream.read_exact(&mut rest).unwrap();
+    if len > 3 {
+        let mut rest = vec![0u8; (len - 3) as usize];
+        stream.read_exact(&mut rest)?;
         response.extend(rest);
     }
 
-    let mut data = Vec::new();
+    Ok(response)
+}
+
+// the write and its read back are two requests, each bounded by the serial timeout
+fn write_serial(
+    stream: &mut Box<dyn SerialPort>,
+    timeout: Duration,
+    protocol: ModbusProtocol,
+    target: &ModbusWrite,
+    value: f64,
+) -> Result<f64, DeviceError> {
+    // a read may have cut the timeout short
+    stream.set_timeout(timeout).map_err(std::io::Error::from)?;
 
-    // check if frame has no Modbus error inside and parse response bools into data vec
-    mreq.parse_u16(&response, &mut data).unwrap();
-    // for i in 0..data.len() {
-    //     println!("{} {}", i, data[i]);
-    // }
+    // drop leftovers of a previous timed out response
+    stream
+        .clear(ClearBuffer::Input)
+        .map_err(std::io::Error::from)?;
 
-    let rounded_number = (data[0] as f64 * 10.0).round() / 100.0;
-    // println!("{} : {}", slave.channel_id, rounded_number);
-    rounded_number
+    match protocol {
+        ModbusProtocol::Rtu => write_value(target, value, ModbusProto::Rtu, |request| {
+            rtu(request, stream)
+        }),
+        ModbusProtocol::Ascii => write_value(target, value, ModbusProto::Ascii, |request| {
+            ascii(request, stream)
+        }),
+        other => Err(DeviceError::Config(format!(
+            "{} is not supported on serial port",
+            other
+        ))),
+    }
 }
 
 #[async_trait]
 impl Device for ModbusDevice {
-    async fn read(self: &mut Self) -> Result<Value, Error> {
-        let mut map = Map::new();
-
-        // 10 seconds timeout
-        let res = tokio::time::timeout(time::Duration::from_secs(10), async {
-            // read registers
-            let config = &self.config;
-            let serial = config.serial.as_ref().unwrap();
-            let modbus = serial.modbus.as_ref().unwrap();
-            let slaves = &modbus.slave;
-
-            for slave in slaves {
-                let rounded_number: f64;
-                if modbus.protocol == "modbus-rtu" {
-                    rounded_number = rtu(slave, &mut self.stream).await;
-                } else {
-                    rounded_number = ascii(slave, &mut self.stream).await;
+    async fn read(self: &mut Self) -> Result<Sample, DeviceError> {
+        // read registers
+        let config = &self.config;
+        let serial = config
+            .serial
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial]".to_string()))?;
+        let modbus = serial
+            .modbus
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial.modbus]".to_string()))?;
+
+        let stream = self.stream.as_mut().ok_or(DeviceError::NotConnected)?;
+
+        let result = read_blocks(
+            config,
+            &modbus.slave,
+            &self.blocks,
+            |block, remaining| {
+                stream
+                    .set_timeout(remaining.min(serial.timeout()))
+                    .map_err(std::io::Error::from)?;
+
+                // drop leftovers of a previous timed out response
+                stream
+                    .clear(ClearBuffer::Input)
+                    .map_err(std::io::Error::from)?;
+
+                match modbus.protocol {
+                    ModbusProtocol::Rtu => {
+                        read_block(block, ModbusProto::Rtu, |request| rtu(request, stream))
+                    }
+                    ModbusProtocol::Ascii => {
+                        read_block(block, ModbusProto::Ascii, |request| ascii(request, stream))
+                    }
+                    other => Err(DeviceError::Config(format!(
+                        "{} is not supported on serial port",
+                        other
+                    ))),
                 }
+            },
+            DeviceError::is_connection_lost,
+        );
 
-                map.insert(
-                    slave.channel_id.clone(),
-                    to_value(rounded_number).expect("Conversion failed"),
-                );
+        // close the broken port, reconnect() will reopen it
+        if let Err(e) = &result {
+            if e.is_connection_lost() {
+                self.stream = None;
             }
-        });
+        }
+
+        result
+    }
+
+    async fn write(self: &mut Self, target: &ModbusWrite, value: f64) -> Result<f64, DeviceError> {
+        let serial = self
+            .config
+            .serial
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial]".to_string()))?;
+        let protocol = serial
+            .modbus
+            .as_ref()
+            .map(|modbus| modbus.protocol)
+            .ok_or(DeviceError::Config("missing [serial.modbus]".to_string()))?;
+
+        let stream = self.stream.as_mut().ok_or(DeviceError::NotConnected)?;
+
+        let result = write_serial(stream, serial.timeout(), protocol, target, value);
+
+        if let Err(e) = &result {
+            if e.is_connection_lost() {
+                self.stream = None;
+            }
+        }
+
+        result
+    }
+
+    async fn reconnect(self: &mut Self) -> Result<(), DeviceError> {
+        let serial = self
+            .config
+            .serial
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial]".to_string()))?;
+
+        // drop the old port first, the os does not allow opening it twice
+        self.stream = None;
+        self.stream = Some(open(serial)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slave(i: usize, id: u16, function: u16, registry: u16, decode_type: DecodeType) -> Slave {
+        Slave {
+            channel_id: format!("ch{}", i),
+            label: format!("ch{}", i),
+            unit: None,
+            color: String::from("#000000"),
+            ror_color: None,
+            id,
+            function,
+            registry,
+            divisor: 1,
+            decode_type,
+            byte_order: None,
+            word_order: None,
+        }
+    }
+
+    fn write_target(
+        decode_type: DecodeType,
+        byte_order: Option<ByteOrder>,
+        word_order: Option<ByteOrder>,
+        divisor: u16,
+    ) -> ModbusWrite {
+        ModbusWrite {
+            device: None,
+            id: 1,
+            function: 16,
+            registry: 0,
+            divisor: Some(divisor),
+            decode_type: Some(decode_type),
+            byte_order,
+            word_order,
+            min: None,
+            max: None,
+        }
+    }
+
+    #[test]
+    fn plan_blocks() {
+        use DecodeType::*;
+
+        // (id, function, registry, decode_type) per slave, max_gap, max_block,
+        // expected (id, function, start, count, slaves) per block
+        type Slaves = Vec<(u16, u16, u16, DecodeType)>;
+        type Blocks = Vec<(u16, u16, u16, u16, Vec<usize>)>;
+        let cases: Vec<(Slaves, Option<u16>, Option<u16>, Blocks)> = vec![
+            (
+                vec![(1, 3, 100, U16), (1, 3, 101, U16), (1, 3, 102, U16)],
+                None,
+                None,
+                vec![(1, 3, 100, 3, vec![0, 1, 2])],
+            ),
+            // a gap is not read without max_gap
+            (
+                vec![(1, 3, 100, U16), (1, 3, 102, U16)],
+                None,
+                None,
+                vec![(1, 3, 100, 1, vec![0]), (1, 3, 102, 1, vec![1])],
+            ),
+            (
+                vec![(1, 3, 100, U16), (1, 3, 102, U16)],
+                Some(1),
+                None,
+                vec![(1, 3, 100, 3, vec![0, 1])],
+            ),
+            (
+                vec![(1, 3, 100, U16), (1, 3, 103, U16)],
+                Some(1),
+                None,
+                vec![(1, 3, 100, 1, vec![0]), (1, 3, 103, 1, vec![1])],
+            ),
+            // max_block splits contiguous registers
+            (
+                vec![(1, 3, 100, U16), (1, 3, 101, U16), (1, 3, 102, U16)],
+                None,
+                Some(2),
+                vec![(1, 3, 100, 2, vec![0, 1]), (1, 3, 102, 1, vec![2])],
+            ),
+            (
+                vec![(1, 3, 100, U16), (1, 3, 101, U16)],
+                None,
+                Some(1),
+                vec![(1, 3, 100, 1, vec![0]), (1, 3, 101, 1, vec![1])],
+            ),
+            // a 32 bit value does not fit the rest of a block
+            (
+                vec![(1, 3, 100, U16), (1, 3, 101, F32)],
+                None,
+                Some(2),
+                vec![(1, 3, 100, 1, vec![0]), (1, 3, 101, 2, vec![1])],
+            ),
+            // unit id and function are never mixed
+            (
+                vec![(1, 3, 100, U16), (1, 4, 101, U16), (2, 3, 102, U16)],
+                None,
+                None,
+                vec![
+                    (1, 3, 100, 1, vec![0]),
+                    (1, 4, 101, 1, vec![1]),
+                    (2, 3, 102, 1, vec![2]),
+                ],
+            ),
+            // registers sorted, blocks in the order of their first channel
+            (
+                vec![(1, 3, 110, U16), (1, 3, 100, U32), (1, 3, 102, I16)],
+                None,
+                None,
+                vec![(1, 3, 110, 1, vec![0]), (1, 3, 100, 3, vec![1, 2])],
+            ),
+            // one register read by two channels
+            (
+                vec![(1, 3, 100, U16), (1, 3, 100, I16)],
+                None,
+                None,
+                vec![(1, 3, 100, 1, vec![0, 1])],
+            ),
+        ];
+
+        for (slaves, max_gap, max_block, expected) in cases {
+            let modbus = Modbus {
+                protocol: ModbusProtocol::Rtu,
+                max_gap,
+                max_block,
+                slave: slaves
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (id, function, registry, decode_type))| {
+                        slave(i, *id, *function, *registry, *decode_type)
+                    })
+                    .collect(),
+            };
+
+            let blocks: Blocks = plan(&modbus)
+                .into_iter()
+                .map(|b| (b.id, b.function, b.start, b.count, b.slaves))
+                .collect();
+            assert_eq!(
+                blocks, expected,
+                "{:?} {:?} {:?}",
+                slaves, max_gap, max_block
+            );
+        }
+    }
+
+    #[test]
+    fn plan_max_block_per_protocol() {
+        use ModbusProtocol::*;
 
-        match res.await {
-            Ok(_) => (),
-            Err(_) => {
-                error!("read_holding_registers timeout");
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "read_holding_registers timeout",
-                ));
+        for (protocol, counts) in [
+            (Rtu, vec![125, 5]),
+            (RtuOverTcp, vec![125, 5]),
+            (Udp, vec![125, 5]),
+            (Tcp, vec![123, 7]),
+            (Ascii, vec![61, 61, 8]),
+        ] {
+            let modbus = Modbus {
+                protocol,
+                max_gap: None,
+                max_block: None,
+                slave: (0..130)
+                    .map(|i| slave(i, 1, 3, i as u16, DecodeType::U16))
+                    .collect(),
+            };
+            let planned: Vec<u16> = plan(&modbus).iter().map(|b| b.count).collect();
+            assert_eq!(planned, counts, "{}", protocol);
+        }
+    }
+
+    // modbus crc, sent low byte first
+    fn crc16(frame: &[u8]) -> u16 {
+        let mut crc = 0xffff_u16;
+        for byte in frame {
+            crc ^= *byte as u16;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xa001
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        crc
+    }
+
+    // answers any request with response
+    struct Loopback {
+        response: std::io::Cursor<Vec<u8>>,
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_full_block() {
+        let modbus = Modbus {
+            protocol: ModbusProtocol::Rtu,
+            max_gap: None,
+            max_block: None,
+            slave: (0..125)
+                .map(|i| slave(i, 1, 3, i as u16, DecodeType::U16))
+                .collect(),
+        };
+        let blocks = plan(&modbus);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].count, 125);
+
+        // the largest rtu frame, 255 bytes
+        let registers: Vec<u16> = (0..125).map(|i| 1000 + i).collect();
+        let mut response = vec![1, 3, 250];
+        registers
+            .iter()
+            .for_each(|r| response.extend_from_slice(&r.to_be_bytes()));
+        let crc = crc16(&response);
+        response.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(response.len(), 255);
+
+        let mut stream = Loopback {
+            response: std::io::Cursor::new(response),
+        };
+        let sample = read_blocks(
+            &Config::new(),
+            &modbus.slave,
+            &blocks,
+            |block, _| read_block(block, ModbusProto::Rtu, |request| rtu(request, &mut stream)),
+            DeviceError::is_connection_lost,
+        )
+        .unwrap();
+
+        assert_eq!(stream.response.position(), 255);
+        let values: Vec<Option<f64>> = sample.channels.iter().map(|c| c.value).collect();
+        let expected: Vec<Option<f64>> = registers.iter().map(|r| Some(*r as f64)).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn decode_orders() {
+        use ByteOrder::*;
+        use DecodeType::*;
+
+        // decode_type, byte_order, word_order, divisor, registers, value,
+        // 100.5 as f32 is 0x42c9_0000
+        type Case = (
+            DecodeType,
+            Option<ByteOrder>,
+            Option<ByteOrder>,
+            u16,
+            Vec<u16>,
+            f64,
+        );
+        let cases: Vec<Case> = vec![
+            (U16, None, None, 1, vec![0x1234], 4660.0),
+            (U16, Some(Little), None, 1, vec![0x3412], 4660.0),
+            (U16, None, None, 10, vec![1234], 123.4),
+            (I16, None, None, 1, vec![0xffff], -1.0),
+            (I16, None, None, 10, vec![0xff9c], -10.0),
+            (I16, Some(Little), None, 1, vec![0x9cff], -100.0),
+            (U32, None, None, 1, vec![0x0001, 0x0002], 65538.0),
+            (U32, None, Some(Big), 1, vec![0x0001, 0x0002], 65538.0),
+            (U32, None, Some(Little), 1, vec![0x0002, 0x0001], 65538.0),
+            (I32, None, None, 1, vec![0xffff, 0xfffe], -2.0),
+            (I32, None, Some(Little), 100, vec![0xfffe, 0xffff], -0.02),
+            (F32, None, None, 1, vec![0x42c9, 0x0000], 100.5),
+            (F32, None, Some(Little), 1, vec![0x0000, 0x42c9], 100.5),
+            (F32, Some(Little), None, 1, vec![0xc942, 0x0000], 100.5),
+            (
+                F32,
+                Some(Little),
+                Some(Little),
+                1,
+                vec![0x0000, 0xc942],
+                100.5,
+            ),
+            // registers after the value are ignored
+            (U16, None, None, 1, vec![7, 8, 9], 7.0),
+        ];
+
+        for (decode_type, byte_order, word_order, divisor, data, expected) in cases {
+            let value =
+                decode_words(decode_type, &byte_order, &word_order, divisor, &data).unwrap();
+            assert!(
+                (value - expected).abs() < 1e-9,
+                "{:?} {:?} {:?} {:x?} : {} != {}",
+                decode_type,
+                byte_order,
+                word_order,
+                data,
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn decode_too_short() {
+        for decode_type in [DecodeType::U32, DecodeType::I32, DecodeType::F32] {
+            assert!(decode_words(decode_type, &None, &None, 1, &[1]).is_err());
+        }
+        assert!(decode_words(DecodeType::U16, &None, &None, 1, &[]).is_err());
+    }
+
+    #[test]
+    fn encode_reverses_decode() {
+        use ByteOrder::*;
+        use DecodeType::*;
+
+        let orders = [None, Some(Big), Some(Little)];
+        let cases = [
+            (U16, 1, 42.0),
+            (U16, 10, 12.3),
+            (I16, 1, -42.0),
+            (I16, 100, -1.5),
+            (U32, 1, 70000.0),
+            (I32, 10, -7000.5),
+            (F32, 1, 100.5),
+            (F32, 10, -0.25),
+        ];
+
+        for (decode_type, divisor, value) in cases {
+            for byte_order in orders {
+                for word_order in orders {
+                    let target = write_target(decode_type, byte_order, word_order, divisor);
+                    let words = encode(&target, value);
+                    assert_eq!(words.len(), decode_type.register_count() as usize);
+
+                    let decoded =
+                        decode_words(decode_type, &byte_order, &word_order, divisor, &words)
+                            .unwrap();
+                    assert!(
+                        (decoded - value).abs() < 1e-6,
+                        "{:?} {:?} {:?} : {} != {}",
+                        decode_type,
+                        byte_order,
+                        word_order,
+                        decoded,
+                        value
+                    );
+                }
             }
         }
-        // println!("result map : {:?} ", map);
-        Ok(Value::Object(map))
+    }
+
+    #[test]
+    fn encode_saturates() {
+        let u16_target = write_target(DecodeType::U16, None, None, 1);
+        assert_eq!(encode(&u16_target, -5.0), vec![0]);
+        assert_eq!(encode(&u16_target, 70000.0), vec![0xffff]);
+        assert_eq!(encode(&u16_target, 41.6), vec![42]);
+
+        let i16_target = write_target(DecodeType::I16, None, None, 1);
+        assert_eq!(encode(&i16_target, -40000.0), vec![0x8000]);
+
+        let f32_target = write_target(DecodeType::F32, None, Some(ByteOrder::Little), 1);
+        assert_eq!(encode(&f32_target, 100.5), vec![0x0000, 0x42c9]);
     }
 }
@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use async_trait::async_trait;
+use rmodbus::{guess_response_frame_len, ModbusProto};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use super::modbus::{plan, read_block, read_blocks, rtu, write_value, Block};
+use super::{Device, DeviceError};
+use crate::config::{Config, ModbusProtocol, ModbusWrite, Tcp};
+use crate::sample::Sample;
+
+// modbus-tcp and rtu-over-tcp share a tcp stream, udp has no connection
+enum Stream {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Stream {
+    fn set_read_timeout(&self, timeout: Duration) -> Result<(), DeviceError> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(Some(timeout))?,
+            Stream::Udp(socket) => socket.set_read_timeout(Some(timeout))?,
+        }
+        Ok(())
+    }
+}
+
+pub struct ModbusTcpDevice {
+    stream: Option<Stream>,
+    config: Config,
+    blocks: Vec<Block>, // requests of one read, see modbus::plan()
+}
+
+impl ModbusTcpDevice {
+    pub fn new(config: Config) -> Result<ModbusTcpDevice, DeviceError> {
+        let tcp = config
+            .tcp
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [tcp]".to_string()))?;
+
+        let blocks = tcp.modbus.as_ref().map(plan).unwrap_or_default();
+
+        let stream = connect(tcp)?;
+
+        Ok(ModbusTcpDevice {
+            stream: Some(stream),
+            config,
+            blocks,
+        })
+    }
+}
+
+fn connect(tcp: &Tcp) -> Result<Stream, DeviceError> {
+    let timeout = tcp.timeout();
+
+    let port_open_error = |message: String| DeviceError::PortOpen {
+        port: format!("{}:{}", tcp.ip, tcp.port),
+        message,
+    };
+
+    let addr = format!("{}:{}", tcp.ip, tcp.port)
+        .to_socket_addrs()
+        .map_err(|e| port_open_error(e.to_string()))?
+        .next()
+        .ok_or(port_open_error("address not resolved".to_string()))?;
+
+    let protocol = tcp.modbus.as_ref().map(|modbus| modbus.protocol);
+    if protocol == Some(ModbusProtocol::Udp) {
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).map_err(|e| port_open_error(e.to_string()))?;
+        // only datagrams of the device are received
+        socket
+            .connect(addr)
+            .map_err(|e| port_open_error(e.to_string()))?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        return Ok(Stream::Udp(socket));
+    }
+
+    let stream =
+        TcpStream::connect_timeout(&addr, timeout).map_err(|e| port_open_error(e.to_string()))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+
+    Ok(Stream::Tcp(stream))
+}
+
+// MBAP header is generated by rmodbus
+fn tcp(request: &[u8], stream: &mut TcpStream) -> Result<Vec<u8>, DeviceError> {
+    stream.write_all(request)?;
+
+    // MBAP header (7 bytes) carries the length of the rest of the frame
+    let mut buf = [0u8; 7];
+    stream.read_exact(&mut buf)?;
+    let mut response = Vec::new();
+    response.extend_from_slice(&buf);
+    let len = guess_response_frame_len(&buf, ModbusProto::TcpUdp)?;
+
+    if len > 7 {
+        let mut rest = vec![0u8; (len - 7) as usize];
+        stream.read_exact(&mut rest)?;
+        response.extend(rest);
+    }
+
+    Ok(response)
+}
+
+// drop late responses of previous requests still queued in the socket
+fn drain(socket: &UdpSocket) -> Result<(), DeviceError> {
+    let mut buf = [0u8; 256];
+    socket.set_nonblocking(true)?;
+    let result = loop {
+        match socket.recv(&mut buf) {
+            Ok(_) => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    socket.set_nonblocking(false)?;
+    result.map_err(DeviceError::from)
+}
+
+// one rtu frame per datagram, crc is checked by rmodbus like on serial
+fn udp(request: &[u8], socket: &UdpSocket) -> Result<Vec<u8>, DeviceError> {
+    drain(socket)?;
+    socket.send(request)?;
+
+    // largest rtu frame is 256 bytes
+    let mut buf = [0u8; 256];
+    let received = socket.recv(&mut buf)?;
+    if received < 3 {
+        return Err(DeviceError::MalformedFrame(format!(
+            "datagram too short : {} bytes",
+            received
+        )));
+    }
+
+    let len = guess_response_frame_len(&buf[..3], ModbusProto::Rtu)? as usize;
+    if received < len {
+        return Err(DeviceError::MalformedFrame(format!(
+            "expected {} bytes, got {}",
+            len, received
+        )));
+    }
+
+    Ok(buf[..len].to_vec())
+}
+
+// run f with the transport of protocol on stream
+fn transact<R, F>(protocol: ModbusProtocol, stream: &mut Stream, f: F) -> Result<R, DeviceError>
+where
+    F: FnOnce(
+        ModbusProto,
+        &mut dyn FnMut(&[u8]) -> Result<Vec<u8>, DeviceError>,
+    ) -> Result<R, DeviceError>,
+{
+    match (protocol, stream) {
+        (ModbusProtocol::Tcp, Stream::Tcp(stream)) => {
+            f(ModbusProto::TcpUdp, &mut |request| tcp(request, stream))
+        }
+        (ModbusProtocol::RtuOverTcp, Stream::Tcp(stream)) => {
+            f(ModbusProto::Rtu, &mut |request| rtu(request, stream))
+        }
+        (ModbusProtocol::Udp, Stream::Udp(socket)) => {
+            f(ModbusProto::Rtu, &mut |request| udp(request, socket))
+        }
+        (other, _) => Err(DeviceError::Config(format!(
+            "{} is not supported on tcp",
+            other
+        ))),
+    }
+}
+
+fn is_stream_broken(e: &DeviceError, is_udp: bool) -> bool {
+    e.is_connection_lost() || (!is_udp && matches!(e, DeviceError::Timeout(_)))
+}
+
+#[async_trait]
+impl Device for ModbusTcpDevice {
+    async fn read(self: &mut Self) -> Result<Sample, DeviceError> {
+        // read registers
+        let config = &self.config;
+        let tcp = config
+            .tcp
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [tcp]".to_string()))?;
+        let modbus = tcp
+            .modbus
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [tcp.modbus]".to_string()))?;
+
+        // a lost datagram is only a failed channel, the next request is a new datagram
+        let is_udp = modbus.protocol == ModbusProtocol::Udp;
+
+        // the stream of a timed out request was dropped, connect again
+        if self.stream.is_none() {
+            self.stream = Some(connect(tcp)?);
+        }
+        let stream = self.stream.as_mut().ok_or(DeviceError::NotConnected)?;
+
+        // a timeout may leave half a frame in the stream, give up this read
+        let result = read_blocks(
+            config,
+            &modbus.slave,
+            &self.blocks,
+            |block, remaining| {
+                stream.set_read_timeout(remaining.min(tcp.timeout()))?;
+                transact(modbus.protocol, stream, |proto, transact| {
+                    read_block(block, proto, transact)
+                })
+            },
+            |e| is_stream_broken(e, is_udp),
+        );
+
+        // start over with a new connection
+        if let Err(e) = &result {
+            if is_stream_broken(e, is_udp) {
+                self.stream = None;
+            }
+        }
+
+        result
+    }
+
+    // the write and its read back are two requests, each bounded by the socket timeout
+    async fn write(self: &mut Self, target: &ModbusWrite, value: f64) -> Result<f64, DeviceError> {
+        let tcp = self
+            .config
+            .tcp
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [tcp]".to_string()))?;
+        let protocol = tcp
+            .modbus
+            .as_ref()
+            .map(|modbus| modbus.protocol)
+            .ok_or(DeviceError::Config("missing [tcp.modbus]".to_string()))?;
+        let is_udp = protocol == ModbusProtocol::Udp;
+
+        // the stream of a timed out request was dropped, connect again
+        if self.stream.is_none() {
+            self.stream = Some(connect(tcp)?);
+        }
+        let stream = self.stream.as_mut().ok_or(DeviceError::NotConnected)?;
+
+        // a read may have cut the timeout short
+        let result = stream.set_read_timeout(tcp.timeout()).and_then(|_| {
+            transact(protocol, stream, |proto, transact| {
+                write_value(target, value, proto, transact)
+            })
+        });
+
+        // start over with a new connection
+        if let Err(e) = &result {
+            if is_stream_broken(e, is_udp) {
+                self.stream = None;
+            }
+        }
+
+        result
+    }
+
+    async fn reconnect(self: &mut Self) -> Result<(), DeviceError> {
+        let tcp = self
+            .config
+            .tcp
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [tcp]".to_string()))?;
+
+        self.stream = None;
+        self.stream = Some(connect(tcp)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use log::info;
+use serde::Serialize;
+use serialport::{SerialPort, SerialPortType};
+use std::fs;
+use std::path::Path;
+
+use super::DeviceError;
+use crate::config::{DataBits, Parity, Serial, StopBits};
+
+impl From<DataBits> for serialport::DataBits {
+    fn from(value: DataBits) -> Self {
+        match value {
+            DataBits::Five => serialport::DataBits::Five,
+            DataBits::Six => serialport::DataBits::Six,
+            DataBits::Seven => serialport::DataBits::Seven,
+            DataBits::Eight => serialport::DataBits::Eight,
+        }
+    }
+}
+
+impl From<Parity> for serialport::Parity {
+    fn from(value: Parity) -> Self {
+        match value {
+            Parity::None => serialport::Parity::None,
+            Parity::Even => serialport::Parity::Even,
+            Parity::Odd => serialport::Parity::Odd,
+        }
+    }
+}
+
+impl From<StopBits> for serialport::StopBits {
+    fn from(value: StopBits) -> Self {
+        match value {
+            StopBits::One => serialport::StopBits::One,
+            StopBits::Two => serialport::StopBits::Two,
+        }
+    }
+}
+
+// port name of [serial], looked up again on every call when [serial.usb] is given,
+// the adapter may come back under another name after replugging
+pub fn resolve_port(serial: &Serial) -> Result<String, DeviceError> {
+    let usb = match &serial.usb {
+        Some(usb) => usb,
+        None => return Ok(serial.port.clone()),
+    };
+
+    if let Some(by_id) = &usb.by_id {
+        // a symlink to the tty, stable across replugging
+        if Path::new(by_id).exists() {
+            return Ok(by_id.clone());
+        }
+        return Err(DeviceError::PortOpen {
+            port: usb.to_string(),
+            message: format!(
+                "{} not found, candidates : {}",
+                by_id,
+                candidates(&by_id_ports())
+            ),
+        });
+    }
+
+    let ports = list_ports()?;
+    let matched: Vec<&PortInfo> = ports
+        .iter()
+        .filter(|p| p.port_type == "usb")
+        .filter(|p| usb.vid.map_or(true, |vid| p.vid == Some(vid)))
+        .filter(|p| usb.pid.map_or(true, |pid| p.pid == Some(pid)))
+        .filter(|p| match &usb.serial_number {
+            Some(serial_number) => p.serial_number.as_ref() == Some(serial_number),
+            None => true,
+        })
+        .collect();
+
+    match matched.as_slice() {
+        [port] => {
+            info!("{} resolved to {}", usb, port.port);
+            Ok(port.port.clone())
+        }
+        [] => Err(DeviceError::PortOpen {
+            port: usb.to_string(),
+            message: format!(
+                "no port matches, candidates : {}",
+                candidates(&ports.iter().map(port_label).collect::<Vec<_>>())
+            ),
+        }),
+        _ => Err(DeviceError::PortOpen {
+            port: usb.to_string(),
+            message: format!(
+                "{} ports match, add serial_number or by_id : {}",
+                matched.len(),
+                candidates(&matched.iter().map(|p| port_label(p)).collect::<Vec<_>>())
+            ),
+        }),
+    }
+}
+
+// "/dev/ttyUSB0 (usb 1a86:7523 USB Serial #1234)"
+fn port_label(p: &PortInfo) -> String {
+    let mut label = format!("{} ({}", p.port, p.port_type);
+    if let (Some(vid), Some(pid)) = (p.vid, p.pid) {
+        label.push_str(&format!(" {:04x}:{:04x}", vid, pid));
+    }
+    if let Some(product) = &p.product {
+        label.push_str(&format!(" {}", product));
+    }
+    if let Some(serial_number) = &p.serial_number {
+        label.push_str(&format!(" #{}", serial_number));
+    }
+    label.push(')');
+    label
+}
+
+fn candidates(labels: &[String]) -> String {
+    if labels.is_empty() {
+        String::from("none")
+    } else {
+        labels.join(", ")
+    }
+}
+
+// entries of /dev/serial/by-id, empty on other platforms
+fn by_id_ports() -> Vec<String> {
+    match fs::read_dir("/dev/serial/by-id") {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// open the port with the settings of [serial], shared by all serial devices
+pub fn open(serial: &Serial) -> Result<Box<dyn SerialPort>, DeviceError> {
+    let port = resolve_port(serial)?;
+
+    let stream = serialport::new(&port, serial.baud_rate)
+        .data_bits(serial.data_bits.into())
+        .parity(serial.parity.into())
+        .stop_bits(serial.stop_bits.into())
+        .timeout(serial.timeout())
+        .open()
+        .map_err(|e| DeviceError::PortOpen {
+            port: port.clone(),
+            message: e.to_string(),
+        })?;
+
+    Ok(stream)
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PortInfo {
+    pub port: String,
+    pub port_type: String, // usb, pci, bluetooth, unknown
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl From<serialport::SerialPortInfo> for PortInfo {
+    fn from(info: serialport::SerialPortInfo) -> Self {
+        let mut port = PortInfo {
+            port: info.port_name,
+            port_type: String::from("unknown"),
+            vid: None,
+            pid: None,
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+        };
+
+        match info.port_type {
+            SerialPortType::UsbPort(usb) => {
+                port.port_type = String::from("usb");
+                port.vid = Some(usb.vid);
+                port.pid = Some(usb.pid);
+                port.manufacturer = usb.manufacturer;
+                port.product = usb.product;
+                port.serial_number = usb.serial_number;
+            }
+            SerialPortType::PciPort => port.port_type = String::from("pci"),
+            SerialPortType::BluetoothPort => port.port_type = String::from("bluetooth"),
+            SerialPortType::Unknown => {}
+        }
+
+        port
+    }
+}
+
+// serial ports present on this machine, sorted by name
+pub fn list_ports() -> Result<Vec<PortInfo>, DeviceError> {
+    let mut ports: Vec<PortInfo> = serialport::available_ports()
+        .map_err(std::io::Error::from)?
+        .into_iter()
+        .map(PortInfo::from)
+        .collect();
+
+    ports.sort_by(|a, b| a.port.cmp(&b.port));
+    Ok(ports)
+}
@@ -1,129 +1,110 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use async_trait::async_trait;
-use log::error;
-use serde_json::{to_value, Map, Value};
-use serialport::{DataBits, Parity, SerialPort, StopBits};
-use std::{
-    io::{Error, ErrorKind},
-    time::Duration,
-};
-use tokio::time;
-
-use super::Device;
-use crate::config::Config;
+use serialport::SerialPort;
+use std::time::Instant;
+
+use super::serial::open;
+use super::{Device, DeviceError};
+use crate::config::{Config, Ta612c};
+use crate::sample::{ChannelReading, Sample};
 
 pub struct Ta612cDevice {
     config: Config,
-    stream: Box<dyn SerialPort>,
+    stream: Option<Box<dyn SerialPort>>,
 }
 
 impl Ta612cDevice {
-    pub fn new(config: Config) -> Ta612cDevice {
-        let serial = config.serial.as_ref().unwrap();
-
-        let timeout = Duration::from_secs(1);
-
-        let mut data_bits = DataBits::Eight;
-        if serial.data_bits == 7 {
-            data_bits = DataBits::Seven;
-        } else if serial.data_bits == 6 {
-            data_bits = DataBits::Six;
-        } else if serial.data_bits == 5 {
-            data_bits = DataBits::Five;
-        }
-
-        let mut parity = Parity::None;
-        let parity_lowercase = serial.parity.to_lowercase();
-        if parity_lowercase == "even" {
-            parity = Parity::Even;
-        } else if parity_lowercase == "odd" {
-            parity = Parity::Odd;
-        }
-
-        let mut stop_bits = StopBits::One;
-        if serial.stop_bits == 2 {
-            stop_bits = StopBits::Two;
-        }
-
-        let stream = serialport::new(&serial.port, serial.baud_rate as u32)
-            .data_bits(data_bits)
-            .parity(parity)
-            .stop_bits(stop_bits)
-            .timeout(timeout)
-            .open()
-            .expect("Failed to open port");
-
-        Ta612cDevice { stream, config }
+    pub fn new(config: Config) -> Result<Ta612cDevice, DeviceError> {
+        let serial = config
+            .serial
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial]".to_string()))?;
+
+        let stream = open(serial)?;
+
+        Ok(Ta612cDevice {
+            stream: Some(stream),
+            config,
+        })
     }
 }
 
+fn read_temperatures(
+    config: &Config,
+    ta612c: &Ta612c,
+    stream: &mut Box<dyn SerialPort>,
+) -> Result<Sample, DeviceError> {
+    let start = Instant::now();
+
+    let request: [u8; 5] = [0xAA, 0x55, 0x01, 0x03, 0x03];
+    stream.write_all(&request)?;
+
+    let mut response: [u8; 13] = [0; 13];
+    stream.read_exact(response.as_mut_slice())?;
+
+    // all 4 channels come in one frame, they share the read latency
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    // T1 ~ T4 at byte 4 ~ 11
+    let channels: Vec<ChannelReading> = ta612c
+        .channel
+        .iter()
+        .take(4)
+        .enumerate()
+        .map(|(i, c)| {
+            let offset = 4 + i * 2;
+            let t = u16::from_ne_bytes([response[offset], response[offset + 1]]) as f64 / 10.0;
+            ChannelReading::good(
+                c.channel_id.clone(),
+                t,
+                config.channel_unit(&c.unit),
+                latency_ms,
+            )
+        })
+        .collect();
+
+    Ok(Sample::new(channels))
+}
+
 #[async_trait]
 impl Device for Ta612cDevice {
-    async fn read(self: &mut Self) -> Result<Value, Error> {
-        let mut map = Map::new();
-
-        // 10 seconds timeout
-        let res = tokio::time::timeout(time::Duration::from_secs(10), async {
-            let config = &self.config;
-            let serial = config.serial.as_ref().unwrap();
-            let ta612c = serial.ta612c.as_ref().unwrap();
-            let channels = &ta612c.channel;
-
-            let request: [u8; 5] = [0xAA, 0x55, 0x01, 0x03, 0x03];
-            let _ = self.stream.write(&request);
-
-            let mut response: [u8; 13] = [0; 13];
-
-            match self.stream.read(response.as_mut_slice()) {
-                Ok(_) => {
-                    let t1 = u16::from_ne_bytes(response[4..6].try_into().unwrap()) as f32 / 10.0;
-                    let t2 = u16::from_ne_bytes(response[6..8].try_into().unwrap()) as f32 / 10.0;
-                    let t3 = u16::from_ne_bytes(response[8..10].try_into().unwrap()) as f32 / 10.0;
-                    let t4 = u16::from_ne_bytes(response[10..12].try_into().unwrap()) as f32 / 10.0;
-
-                    for (i, c) in channels.iter().enumerate() {
-                        if i == 0 {
-                            map.insert(
-                                c.channel_id.clone(),
-                                to_value(t1).expect("Conversion failed"),
-                            );
-                        }
-                        if i == 1 {
-                            map.insert(
-                                c.channel_id.clone(),
-                                to_value(t2).expect("Conversion failed"),
-                            );
-                        }
-                        if i == 2 {
-                            map.insert(
-                                c.channel_id.clone(),
-                                to_value(t3).expect("Conversion failed"),
-                            );
-                        }
-                        if i == 3 {
-                            map.insert(
-                                c.channel_id.clone(),
-                                to_value(t4).expect("Conversion failed"),
-                            );
-                        }
-                    }
-                }
-                Err(_) => {}
-            }
-        });
-
-        match res.await {
-            Ok(_) => (),
-            Err(_) => {
-                error!("read_holding_registers timeout");
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "read_holding_registers timeout",
-                ));
+    // one request and one response, bounded by the serial timeout
+    async fn read(self: &mut Self) -> Result<Sample, DeviceError> {
+        let config = &self.config;
+        let serial = config
+            .serial
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial]".to_string()))?;
+        let ta612c = serial
+            .ta612c
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial.ta612c]".to_string()))?;
+
+        let stream = self.stream.as_mut().ok_or(DeviceError::NotConnected)?;
+
+        let result = read_temperatures(config, ta612c, stream);
+
+        // close the broken port, reconnect() will reopen it
+        if let Err(e) = &result {
+            if e.is_connection_lost() {
+                self.stream = None;
             }
         }
-        // println!("result map : {:?} ", map);
-        Ok(Value::Object(map))
+
+        result
+    }
+
+    async fn reconnect(self: &mut Self) -> Result<(), DeviceError> {
+        let serial = self
+            .config
+            .serial
+            .as_ref()
+            .ok_or(DeviceError::Config("missing [serial]".to_string()))?;
+
+        // drop the old port first, the os does not allow opening it twice
+        self.stream = None;
+        self.stream = Some(open(serial)?);
+        Ok(())
     }
 }
@@ -0,0 +1,239 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// log samples without webview, for headless machines like a Raspberry Pi
+//
+// roastcraft --headless [--config=roastcraft.toml] [--format=csv|json] [--output=samples.csv]
+//
+// without --config, the config is searched like the app does, except the app config dir
+// and the last used config, see config/discovery.rs
+//
+// samples are written to stdout unless --output is given, logs go to stderr,
+// Ctrl+C (SIGINT) stops logging and flushes the output
+
+use log::{error, info, warn, LevelFilter};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+use tauri_plugin_log::fern;
+
+use crate::config::{self, discovery, Config};
+use crate::devices::{self, Reader};
+use crate::sample::{Quality, Sample};
+
+#[derive(PartialEq)]
+enum Format {
+    Csv,
+    Json, // one Sample per line
+}
+
+struct Options {
+    config_file_name: Option<String>,
+    format: Format,
+    output: Option<String>,
+}
+
+// accept both --key=value and --key value
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        config_file_name: None,
+        format: Format::Csv,
+        output: None,
+    };
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let (key, inline_value) = match arg.split_once('=') {
+            Some((k, v)) => (k, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+
+        if key == "--headless" {
+            continue;
+        }
+
+        let mut value = || match &inline_value {
+            Some(v) => Ok(v.clone()),
+            None => iter
+                .next()
+                .cloned()
+                .ok_or(format!("missing value for {}", key)),
+        };
+
+        match key {
+            "-c" | "--config" => options.config_file_name = Some(value()?),
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    other => return Err(format!("unknown format {}, use csv or json", other)),
+                }
+            }
+            "--output" => options.output = Some(value()?),
+            other => return Err(format!("unknown argument {}", other)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn write_header(out: &mut dyn Write, channel_ids: &[String]) -> io::Result<()> {
+    writeln!(out, "timestamp,elapsed_ms,{}", channel_ids.join(","))?;
+    out.flush()
+}
+
+fn write_sample(
+    out: &mut dyn Write,
+    format: &Format,
+    channel_ids: &[String],
+    sample: &Sample,
+) -> io::Result<()> {
+    match format {
+        Format::Csv => {
+            // bad or missing channels are left empty
+            let values: Vec<String> = channel_ids
+                .iter()
+                .map(|id| {
+                    sample
+                        .channels
+                        .iter()
+                        .find(|c| &c.channel_id == id && c.quality == Quality::Good)
+                        .and_then(|c| c.value)
+                        .map(|v| v.to_string())
+                        .unwrap_or_default()
+                })
+                .collect();
+            writeln!(
+                out,
+                "{},{},{}",
+                sample.timestamp,
+                sample.elapsed_ms,
+                values.join(",")
+            )?;
+        }
+        Format::Json => {
+            writeln!(out, "{}", serde_json::to_string(sample)?)?;
+        }
+    }
+
+    // flush every sample, nothing is lost when the process is killed
+    out.flush()
+}
+
+// same readers and merger as main, without frontend events
+async fn acquire(config: Config, format: &Format, out: &mut dyn Write) -> Result<(), String> {
+    let channel_ids = config.channel_ids();
+    let sample_interval = config.sample_interval();
+
+    let mut readers = Vec::new();
+    for (name, device_config) in config.devices() {
+        let device =
+            devices::new_device(device_config.clone()).map_err(|e| format!("{} : {}", name, e))?;
+        info!(
+            "device {} connected, sample interval {:?}",
+            name,
+            device_config.sample_interval()
+        );
+        // no slider to set manual channels from, writing is disabled
+        let (reader, _) = Reader::new(name, device_config, device);
+        readers.push(reader);
+    }
+
+    if *format == Format::Csv {
+        write_header(out, &channel_ids).map_err(|e| e.to_string())?;
+    }
+
+    let recording_start = Instant::now();
+
+    // read errors are logged by the readers
+    devices::acquire(
+        readers,
+        channel_ids.clone(),
+        sample_interval,
+        |mut sample, read_at| {
+            sample.elapsed_ms = read_at
+                .saturating_duration_since(recording_start)
+                .as_millis() as u64;
+            write_sample(out, format, &channel_ids, &sample).map_err(|e| e.to_string())
+        },
+        |_| {},
+    )
+    .await
+}
+
+// returns process exit code
+pub fn run(args: &[String]) -> i32 {
+    let _ = fern::Dispatch::new()
+        .format(|out, message, record| out.finish(format_args!("[{}] {}", record.level(), message)))
+        .level(LevelFilter::Info)
+        .chain(io::stderr())
+        .apply();
+
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            error!("{}", e);
+            return 2;
+        }
+    };
+
+    let config_file_name = discovery::find(options.config_file_name.as_deref(), None)
+        .to_string_lossy()
+        .to_string();
+
+    let config = match config::load(&config_file_name) {
+        Ok((config, errors)) => {
+            for e in &errors {
+                if e.fatal {
+                    error!("{}", e);
+                } else {
+                    warn!("{}", e);
+                }
+            }
+            if config::has_fatal(&errors) {
+                error!("config has fatal errors, logging refused");
+                return 1;
+            }
+            config
+        }
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+
+    let mut out: Box<dyn Write + Send> = match &options.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                error!("Failed to create {} : {}", path, e);
+                return 1;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    info!(
+        "headless logging {} {}, press Ctrl+C to stop",
+        config.brand, config.model
+    );
+
+    let result = tauri::async_runtime::block_on(async {
+        tokio::select! {
+            result = acquire(config, &options.format, out.as_mut()) => result,
+            _ = tokio::signal::ctrl_c() => {
+                info!("interrupted, stop logging");
+                Ok(())
+            }
+        }
+    });
+
+    let _ = out.flush();
+
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("{}", e);
+            1
+        }
+    }
+}
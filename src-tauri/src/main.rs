@@ -3,88 +3,230 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use log::{debug, trace, warn, LevelFilter};
-use std::fs::File;
-use std::io::Read;
+use log::{debug, error, info, trace, warn, LevelFilter};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Mutex;
-use tauri::async_runtime::{spawn, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use tauri::async_runtime::{spawn, spawn_blocking, JoinHandle};
 use tauri::{CustomMenuItem, Manager, Menu, MenuItem, Submenu};
 use tauri_plugin_log::{fern::colors::ColoredLevelConfig, LogTarget};
-use tokio::time::{interval, Duration};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tokio::time::{interval, timeout};
 
-use crate::config::Config;
-use crate::devices::Device;
+use crate::config::discovery::{self, ConfigFile};
+use crate::config::presets::MachinePreset;
+use crate::config::{Config, ConfigError};
+use crate::devices::serial::PortInfo;
+use crate::devices::{
+    unix_millis, ConnectionState, Device, DeviceError, DeviceStatus, Reader, WriteRequest,
+};
+use crate::pid::{Controller, PidStatus, ProfilePoint};
+use crate::recorder::{JournalEntry, Recorder, RecoveredSession};
+use crate::sample::Sample;
 
 mod config;
 mod devices;
+mod headless;
+mod pid;
+mod recorder;
+mod sample;
+
+// how often the config file is checked for changes, see watch_config
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(1);
 
 struct RoastCraftState {
     reader_handle: Option<JoinHandle<()>>,
+    opening_devices: bool, // button_on_clicked is opening devices, without holding the state
     config: Config,
+    config_errors: Vec<ConfigError>, // validation result of config, acquisition refuses fatal errors
+    config_path: String,
+    config_modified: Option<SystemTime>, // modified time of config_path when it was loaded
+    device_status: Vec<DeviceStatus>,    // one per device of Config::devices()
+    device_writers: HashMap<String, UnboundedSender<WriteRequest>>, // by device name, while reading
+    recording_start: Instant,            // origin of Sample.elapsed_ms
+    recorder: Option<Recorder>,
+    pid: Option<Controller>, // armed or paused
 }
 
 impl RoastCraftState {
     fn new() -> Self {
         Self {
             reader_handle: None,
+            opening_devices: false,
             config: Config::new(),
+            config_errors: Vec::new(),
+            config_path: String::new(),
+            config_modified: None,
+            device_status: Vec::new(),
+            device_writers: HashMap::new(),
+            recording_start: Instant::now(),
+            recorder: None,
+            pid: None,
+        }
+    }
+}
+
+// keep a copy in state for get_device_status, and notify frontend
+fn update_device_status(app: &tauri::AppHandle, status: &DeviceStatus) {
+    {
+        let state_mutex = app.state::<Mutex<RoastCraftState>>();
+        let mut state = state_mutex.lock().unwrap();
+        match state
+            .device_status
+            .iter_mut()
+            .find(|s| s.device == status.device)
+        {
+            Some(s) => *s = status.clone(),
+            None => state.device_status.push(status.clone()),
+        }
+    }
+
+    app.emit_all("device_status", status).unwrap();
+    trace!("event device_status emitted : {:?}", status);
+}
+
+type OpenedDevice = (String, Config, Box<dyn Device + Send>);
+
+// open every device first, nothing is read unless all of them are there,
+// on failure the names opened so far are returned and their devices closed again
+fn open_devices(config: &Config) -> Result<Vec<OpenedDevice>, (Vec<String>, String, DeviceError)> {
+    let mut opened: Vec<OpenedDevice> = Vec::new();
+    for (name, device_config) in config.devices() {
+        match devices::new_device(device_config.clone()) {
+            Ok(device) => opened.push((name, device_config, device)),
+            Err(e) => {
+                let names = opened.into_iter().map(|(name, _, _)| name).collect();
+                return Err((names, name, e));
+            }
         }
     }
+    Ok(opened)
 }
 
 #[tauri::command]
-async fn button_on_clicked(app: tauri::AppHandle) -> () {
+async fn button_on_clicked(app: tauri::AppHandle) -> Result<(), String> {
     trace!("command called : button_on_clicked");
 
     let app2 = app.clone();
 
+    let config = {
+        let state_mutex = app.state::<Mutex<RoastCraftState>>();
+        let mut state = state_mutex.lock().unwrap();
+
+        if config::has_fatal(&state.config_errors) {
+            let fatal: Vec<String> = state
+                .config_errors
+                .iter()
+                .filter(|e| e.fatal)
+                .map(|e| e.to_string())
+                .collect();
+            error!("config has fatal errors, acquisition refused");
+            return Err(format!(
+                "Config has errors, fix them and restart \n{}",
+                fatal.join("\n")
+            ));
+        }
+
+        if state.reader_handle.is_some() || state.opening_devices {
+            warn!("reader_handle already exist");
+            return Ok(());
+        }
+        state.opening_devices = true;
+        state.config.clone()
+    };
+
+    // opening a port or connecting blocks up to its timeout, the state is not held meanwhile
+    let devices_config = config.clone();
+    let opened = spawn_blocking(move || open_devices(&devices_config)).await;
+
     let state_mutex = app.state::<Mutex<RoastCraftState>>();
     let mut state = state_mutex.lock().unwrap();
+    state.opening_devices = false;
 
-    let config = state.config.clone();
-    match &state.reader_handle {
-        Some(_handle) => warn!("reader_handle already exist"),
-        None => {
-            state.reader_handle = Some(spawn(async move {
-                let mut interval = interval(Duration::from_secs(2));
+    let opened = match opened {
+        Ok(Ok(opened)) => opened,
+        Ok(Err((opened, name, e))) => {
+            error!("failed to create device {} : {}", name, e);
+            // the devices opened before were closed again when open_devices gave up
+            state.device_status = opened.iter().map(|name| DeviceStatus::new(name)).collect();
+            let mut status = DeviceStatus::new(&name);
+            status.read_failed(&e);
+            status.disconnected();
+            state.device_status.push(status);
+            for status in &state.device_status {
+                app.emit_all("device_status", status).unwrap();
+            }
+            return Err(format!("{} : {}", name, e));
+        }
+        Err(e) => {
+            error!("failed to open devices : {}", e);
+            return Err(e.to_string());
+        }
+    };
 
-                let mut device: Box<dyn Device + Send>;
+    let mut readers = Vec::new();
+    let mut writers = HashMap::new();
+    state.device_status = Vec::new();
+    for (name, device_config, device) in opened {
+        state.device_status.push(DeviceStatus::connected(&name));
+        let (reader, writer) = Reader::new(name.clone(), device_config, device);
+        readers.push(reader);
+        writers.insert(name, writer);
+    }
 
-                // serial has priority over tcp
-                match config.serial.clone() {
-                    Some(serial) => match serial.modbus {
-                        Some(_) => {
-                            device = Box::new(devices::modbus::ModbusDevice::new(config));
-                        }
-                        None => {
-                            device = Box::new(devices::ta612c::Ta612cDevice::new(config));
-                        }
-                    },
-                    None => {
-                        device = Box::new(devices::http::HttpDevice::new(config));
-                    }
-                }
+    for status in &state.device_status {
+        app.emit_all("device_status", status).unwrap();
+    }
+    state.device_writers = writers;
+    state.recording_start = Instant::now();
 
-                loop {
-                    interval.tick().await;
-                    trace!("i am inside async process, 2 sec interval");
+    let app3 = app.clone();
+    state.reader_handle = Some(spawn(async move {
+        let result = devices::acquire(
+            readers,
+            config.channel_ids(),
+            config.sample_interval(),
+            |mut sample, read_at| {
+                {
+                    let state_mutex = app2.state::<Mutex<RoastCraftState>>();
+                    let mut state = state_mutex.lock().unwrap();
 
-                    match device.read().await {
-                        Ok(json_value) => {
-                            app2.emit_all("read_channels", &json_value).unwrap();
-                            trace!("event read_channels emitted : {}", json_value);
+                    // from when the device was read, not when the sample got here
+                    sample.elapsed_ms = read_at
+                        .saturating_duration_since(state.recording_start)
+                        .as_millis() as u64;
+
+                    if let Some(recorder) = state.recorder.as_ref() {
+                        if let Err(e) = recorder.append(JournalEntry::Sample(sample.clone())) {
+                            error!("{}", e);
                         }
-                        Err(_) => {}
                     }
+
+                    step_pid(&app2, &mut state, &sample);
                 }
-            }));
 
-            debug!(
-                "spawned reader_handle : {:?}",
-                state.reader_handle.as_ref().unwrap()
-            )
+                app2.emit_all("read_channels", &sample).unwrap();
+                trace!("event read_channels emitted : {:?}", sample);
+                Ok(())
+            },
+            move |status| update_device_status(&app3, status),
+        )
+        .await;
+
+        if let Err(e) = result {
+            error!("{}", e);
         }
-    }
+    }));
+
+    debug!(
+        "spawned reader_handle : {:?}",
+        state.reader_handle.as_ref().unwrap()
+    );
+
+    Ok(())
 }
 
 #[tauri::command]
@@ -94,17 +236,637 @@ async fn button_off_clicked(app: tauri::AppHandle) -> () {
     let state_mutex = app.state::<Mutex<RoastCraftState>>();
     let mut state = state_mutex.lock().unwrap();
 
-    match &state.reader_handle {
-        Some(handle) => {
-            handle.abort();
-            debug!(
-                "aborted reader_handle : {:?}",
-                state.reader_handle.as_ref().unwrap()
-            );
-            state.reader_handle = None;
+    if state.reader_handle.is_none() {
+        warn!("reader_handle is None");
+    }
+    stop_reader(&app, &mut state);
+}
+
+// abort the reader, the device is closed when the reader task is dropped
+fn stop_reader(app: &tauri::AppHandle, state: &mut RoastCraftState) {
+    if let Some(handle) = state.reader_handle.take() {
+        handle.abort();
+        debug!("aborted reader_handle : {:?}", handle);
+    }
+    state.device_writers.clear();
+    disarm_pid(app, state);
+
+    // no more samples, close a journal the frontend did not stop
+    if let Some(recorder) = state.recorder.take() {
+        warn!("recorder still exist, stop it");
+        if let Err(e) = recorder.stop() {
+            error!("{}", e);
+        }
+    }
+
+    for status in state.device_status.iter_mut() {
+        status.disconnected();
+        app.emit_all("device_status", &*status).unwrap();
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// load and validate a config file, then stop the reader and swap the config,
+// the current config is kept when the new one fails to parse or has fatal errors,
+// refused while recording, stopping the reader would end the roast
+fn switch_config(app: &tauri::AppHandle, path: &str) -> Result<Vec<ConfigError>, String> {
+    let (config, errors) = config::load(path)?;
+    for e in &errors {
+        warn!("{}", e);
+    }
+    if config::has_fatal(&errors) {
+        return Err(config::errors_message(path, &errors));
+    }
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let mut state = state_mutex.lock().unwrap();
+
+    if state.recorder.is_some() {
+        return Err("Recording, stop it before loading config".to_string());
+    }
+    stop_reader(app, &mut state);
+
+    state.config = config;
+    state.config_errors = errors.clone();
+    state.config_path = path.to_string();
+    state.config_modified = modified_time(path);
+    info!("config switched to {}", path);
+
+    // started with this config next time, see config/discovery.rs
+    match app.path_resolver().app_config_dir() {
+        Some(dir) => {
+            if let Err(e) = discovery::remember(&dir, path) {
+                warn!("{}", e);
+            }
+        }
+        None => warn!("Failed to resolve app config dir"),
+    }
+
+    // frontend rebuilds its channels from get_config
+    app.emit_all("config_changed", &state.config).unwrap();
+
+    Ok(errors)
+}
+
+#[tauri::command]
+async fn load_config(app: tauri::AppHandle, path: String) -> Result<Vec<ConfigError>, String> {
+    trace!("command called : load_config");
+
+    switch_config(&app, &path)
+}
+
+#[tauri::command]
+async fn reload_config(app: tauri::AppHandle) -> Result<Vec<ConfigError>, String> {
+    trace!("command called : reload_config");
+
+    let path = {
+        let state_mutex = app.state::<Mutex<RoastCraftState>>();
+        let state = state_mutex.lock().unwrap();
+        state.config_path.clone()
+    };
+
+    switch_config(&app, &path)
+}
+
+// write the config edited in frontend to the current config file, and switch to it
+#[tauri::command]
+async fn save_config(app: tauri::AppHandle, config: Config) -> Result<Vec<ConfigError>, String> {
+    trace!("command called : save_config");
+
+    let path = {
+        let state_mutex = app.state::<Mutex<RoastCraftState>>();
+        let state = state_mutex.lock().unwrap();
+
+        // switching config stops the reader and the journal
+        if state.recorder.is_some() {
+            return Err("Recording, stop it before saving config".to_string());
+        }
+        state.config_path.clone()
+    };
+
+    config::save(&path, &config)?;
+
+    // read back what was written, the running config is exactly the file
+    switch_config(&app, &path)
+}
+
+// write the migrated config back to its file and reload it, false if nothing to migrate
+#[tauri::command]
+async fn migrate_config(app: tauri::AppHandle) -> Result<bool, String> {
+    trace!("command called : migrate_config");
+
+    let path = {
+        let state_mutex = app.state::<Mutex<RoastCraftState>>();
+        let state = state_mutex.lock().unwrap();
+
+        // the migrated file is loaded right away, see switch_config
+        if state.recorder.is_some() {
+            return Err("Recording, stop it before migrating config".to_string());
         }
-        None => warn!("reader_handle is None"),
+        state.config_path.clone()
+    };
+
+    let migrated = config::write_migrated(&path)?;
+    if migrated {
+        switch_config(&app, &path)?;
     }
+    Ok(migrated)
+}
+
+// config files in the app config dir, where create_config_from_preset writes to
+#[tauri::command]
+async fn list_configs(app: tauri::AppHandle) -> Result<Vec<ConfigFile>, String> {
+    trace!("command called : list_configs");
+
+    let dir = app
+        .path_resolver()
+        .app_config_dir()
+        .ok_or("Failed to resolve app config dir".to_string())?;
+
+    Ok(discovery::list(&dir))
+}
+
+#[tauri::command]
+async fn list_machine_presets() -> Vec<MachinePreset> {
+    trace!("command called : list_machine_presets");
+
+    config::presets::list()
+}
+
+// write a config for the machine into the app config dir, returns its path for load_config
+#[tauri::command]
+async fn create_config_from_preset(
+    app: tauri::AppHandle,
+    brand: String,
+    model: String,
+    port: String,
+) -> Result<String, String> {
+    trace!("command called : create_config_from_preset");
+
+    let dir = app
+        .path_resolver()
+        .app_config_dir()
+        .ok_or("Failed to resolve app config dir".to_string())?;
+
+    config::presets::create(&dir, &brand, &model, &port).map(|p| p.to_string_lossy().to_string())
+}
+
+// reload the config when its file changes while the device is off, if watch_config is set
+async fn watch_config(app: tauri::AppHandle) {
+    let mut interval = interval(CONFIG_WATCH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let path = {
+            let state_mutex = app.state::<Mutex<RoastCraftState>>();
+            let mut state = state_mutex.lock().unwrap();
+
+            // while reading or recording the change waits, it is picked up once stopped
+            if !state.config.watch_config.unwrap_or(false)
+                || state.reader_handle.is_some()
+                || state.recorder.is_some()
+            {
+                continue;
+            }
+
+            let modified = modified_time(&state.config_path);
+            if modified.is_none() || modified == state.config_modified {
+                continue;
+            }
+            // a broken file is reported once, not on every tick
+            state.config_modified = modified;
+            state.config_path.clone()
+        };
+
+        info!("{} changed, reload config", path);
+        if let Err(msg) = switch_config(&app, &path) {
+            error!("{}", msg);
+            let main_window = app.get_window("main");
+            tauri::api::dialog::message(main_window.as_ref(), "RoastCraft", msg);
+        }
+    }
+}
+
+#[tauri::command]
+async fn list_serial_ports() -> Result<Vec<PortInfo>, String> {
+    trace!("command called : list_serial_ports");
+
+    devices::serial::list_ports().map_err(|e| e.to_string())
+}
+
+// open the configured device, optionally on another serial port, read once and close it again
+#[tauri::command]
+async fn test_device_connection(
+    app: tauri::AppHandle,
+    port: Option<String>,
+) -> Result<Sample, String> {
+    trace!("command called : test_device_connection");
+
+    let mut config = {
+        let state_mutex = app.state::<Mutex<RoastCraftState>>();
+        let state = state_mutex.lock().unwrap();
+
+        // the port is held by the reader
+        if state.reader_handle.is_some() {
+            return Err("Device is in use, turn it off before testing".to_string());
+        }
+        state.config.clone()
+    };
+
+    // the port is of the first serial device
+    if let Some(port) = port {
+        let serial = match config.serial.as_mut() {
+            Some(serial) => Some(serial),
+            None => config
+                .device
+                .iter_mut()
+                .flatten()
+                .find_map(|d| d.serial.as_mut()),
+        };
+        match serial {
+            Some(serial) => {
+                serial.port = port;
+                serial.usb = None;
+            }
+            None => return Err("Config has no serial device, port cannot be changed".to_string()),
+        }
+    }
+
+    // one after another, a sample with the channels of all devices
+    let mut channels = Vec::new();
+    for (name, device_config) in config.devices() {
+        let mut device =
+            devices::new_device(device_config).map_err(|e| format!("{} : {}", name, e))?;
+
+        match device.read().await {
+            Ok(sample) => channels.extend(sample.channels),
+            Err(e) => {
+                warn!(
+                    "test device {} connection failed ({}) : {}",
+                    name,
+                    e.kind(),
+                    e
+                );
+                return Err(format!("{} : {}", name, e));
+            }
+        }
+    }
+
+    let mut sample = Sample::new(channels);
+    sample.timestamp = unix_millis();
+    debug!("test device connection : {:?}", sample);
+    Ok(sample)
+}
+
+// a write queued on a reader, answered with the value read back
+struct PendingWrite {
+    device: String,
+    value: f64, // clamped
+    timeout: Duration,
+    response: oneshot::Receiver<Result<f64, DeviceError>>,
+}
+
+impl PendingWrite {
+    async fn read_back(self) -> Result<f64, String> {
+        match timeout(self.timeout, self.response).await {
+            Ok(Ok(Ok(value))) => Ok(value),
+            Ok(Ok(Err(e))) => Err(format!("{} : {}", self.device, e)),
+            Ok(Err(_)) => Err(format!("{} : reader stopped", self.device)),
+            Err(_) => Err(format!(
+                "{} : no read back within {:?}",
+                self.device, self.timeout
+            )),
+        }
+    }
+}
+
+// queue a write of a manual channel on the reader of its modbus device
+fn send_manual_channel(
+    state: &RoastCraftState,
+    channel_id: &str,
+    value: f64,
+) -> Result<PendingWrite, String> {
+    let mc = state
+        .config
+        .manual_channel
+        .iter()
+        .flatten()
+        .find(|mc| mc.channel_id == channel_id)
+        .ok_or(format!("Manual channel {} not found", channel_id))?;
+    let target = mc.modbus.clone().ok_or(format!(
+        "Manual channel {} has no [manual_channel.modbus]",
+        channel_id
+    ))?;
+    let device = state
+        .config
+        .write_device(&target)
+        .ok_or(format!("No modbus device to write {} to", channel_id))?;
+    let writer = state
+        .device_writers
+        .get(&device)
+        .ok_or("Device is off, turn it on before setting channels".to_string())?;
+
+    // the reader is reconnecting, the write would only wait for it
+    let disconnected = state
+        .device_status
+        .iter()
+        .any(|s| s.device == device && s.state == ConnectionState::Disconnected);
+    if disconnected {
+        return Err(format!("{} is disconnected", device));
+    }
+
+    // a read in progress is finished before the write, then the write is read back
+    let read_back_timeout = state
+        .config
+        .devices()
+        .into_iter()
+        .find(|(name, _)| *name == device)
+        .map(|(_, c)| c.read_timeout() * 2)
+        .unwrap_or(state.config.read_timeout() * 2);
+
+    let value = target.clamp(mc, value);
+    let (reply, response) = oneshot::channel();
+    writer
+        .send(WriteRequest {
+            target,
+            value,
+            reply,
+        })
+        .map_err(|_| format!("{} : reader stopped", device))?;
+
+    Ok(PendingWrite {
+        device,
+        value,
+        timeout: read_back_timeout,
+        response,
+    })
+}
+
+// write a manual channel to the roaster through its modbus device, returns the value read back
+#[tauri::command]
+async fn set_manual_channel(
+    app: tauri::AppHandle,
+    channel_id: String,
+    value: f64,
+) -> Result<f64, String> {
+    trace!("command called : set_manual_channel");
+
+    let pending = {
+        let state_mutex = app.state::<Mutex<RoastCraftState>>();
+        let state = state_mutex.lock().unwrap();
+
+        // the next output of the controller would overwrite it
+        if let Some(pid) = state.pid.as_ref() {
+            if pid.is_armed() && pid.status.output == channel_id {
+                return Err(format!(
+                    "{} is controlled by pid, pause it first",
+                    channel_id
+                ));
+            }
+        }
+
+        send_manual_channel(&state, &channel_id, value)?
+    };
+
+    let (device, value) = (pending.device.clone(), pending.value);
+    let confirmed = pending.read_back().await?;
+    info!(
+        "manual channel {} set to {} on {}, read back {}",
+        channel_id, value, device, confirmed
+    );
+
+    // a paused controller resumes from here
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let mut state = state_mutex.lock().unwrap();
+    if let Some(pid) = state.pid.as_mut() {
+        if pid.status.output == channel_id {
+            pid.written(Ok(confirmed));
+        }
+    }
+
+    Ok(confirmed)
+}
+
+// step the controller with a merged sample, its output is written and confirmed in background
+fn step_pid(app: &tauri::AppHandle, state: &mut RoastCraftState, sample: &Sample) {
+    let (channel_id, output) = match state.pid.as_mut() {
+        Some(pid) => (pid.status.output.clone(), pid.step(sample)),
+        None => return,
+    };
+
+    let sent = output.map(|value| send_manual_channel(state, &channel_id, value));
+
+    if let Some(pid) = state.pid.as_mut() {
+        match sent {
+            Some(Ok(pending)) => {
+                let app = app.clone();
+                spawn(async move {
+                    let result = pending.read_back().await;
+                    if let Err(e) = &result {
+                        error!("pid output {} : {}", channel_id, e);
+                    }
+
+                    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+                    let mut state = state_mutex.lock().unwrap();
+                    if let Some(pid) = state.pid.as_mut() {
+                        pid.written(result);
+                        app.emit_all("pid_status", &pid.status).unwrap();
+                    }
+                });
+            }
+            Some(Err(e)) => {
+                error!("pid output {} : {}", channel_id, e);
+                pid.written(Err(e));
+            }
+            None => {}
+        }
+
+        app.emit_all("pid_status", &pid.status).unwrap();
+        trace!("event pid_status emitted : {:?}", pid.status);
+    }
+}
+
+// profile is the BT of a ghost with seconds since its CHARGE, time is seconds since CHARGE of
+// this roast, without profile [[pid.segment]] of config are followed from now on,
+// output is the current value of the manual channel the controller starts from
+#[tauri::command]
+async fn pid_arm(
+    app: tauri::AppHandle,
+    profile: Option<Vec<ProfilePoint>>,
+    time: f64,
+    output: f64,
+) -> Result<(), String> {
+    trace!("command called : pid_arm");
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let mut state = state_mutex.lock().unwrap();
+
+    if state.reader_handle.is_none() {
+        return Err("Device is off, turn it on before arming pid".to_string());
+    }
+
+    let controller = Controller::arm(&state.config, profile, time, output)?;
+    info!(
+        "pid armed, {} steers {}",
+        controller.status.output, controller.status.input
+    );
+    app.emit_all("pid_status", &controller.status).unwrap();
+    state.pid = Some(controller);
+
+    Ok(())
+}
+
+// pause leaves the output to the slider, resume continues from its value
+#[tauri::command]
+async fn pid_pause(app: tauri::AppHandle, paused: bool) -> Result<(), String> {
+    trace!("command called : pid_pause");
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let mut state = state_mutex.lock().unwrap();
+
+    let pid = state.pid.as_mut().ok_or("Pid is not armed".to_string())?;
+    if paused {
+        pid.pause();
+        info!("pid paused");
+    } else {
+        pid.resume();
+        info!("pid resumed");
+    }
+    app.emit_all("pid_status", &pid.status).unwrap();
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn pid_disarm(app: tauri::AppHandle) {
+    trace!("command called : pid_disarm");
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let mut state = state_mutex.lock().unwrap();
+
+    disarm_pid(&app, &mut state);
+}
+
+// the output keeps its last written value
+fn disarm_pid(app: &tauri::AppHandle, state: &mut RoastCraftState) {
+    if state.pid.take().is_none() {
+        return;
+    }
+    info!("pid disarmed");
+
+    if let Some(pid) = &state.config.pid {
+        app.emit_all("pid_status", &PidStatus::disarmed(pid))
+            .unwrap();
+    }
+}
+
+fn journal_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app.path_resolver()
+        .app_data_dir()
+        .map(|dir| dir.join("journal"))
+        .ok_or("Failed to resolve app data dir".to_string())
+}
+
+#[tauri::command]
+async fn start_recording(app: tauri::AppHandle) -> Result<(), String> {
+    trace!("command called : start_recording");
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let mut state = state_mutex.lock().unwrap();
+
+    // the curve starts now whether or not the journal can be written
+    state.recording_start = Instant::now();
+
+    if let Some(recorder) = state.recorder.take() {
+        warn!("recorder already exist, stop it first");
+        if let Err(e) = recorder.stop() {
+            error!("failed to stop recorder : {}", e);
+        }
+    }
+
+    let started = journal_dir(&app)
+        .and_then(|dir| Recorder::start(&dir, &state.config.brand, &state.config.model));
+    match started {
+        Ok(recorder) => {
+            state.recorder = Some(recorder);
+            Ok(())
+        }
+        Err(e) => {
+            error!("recording without journal : {}", e);
+            Err(e)
+        }
+    }
+}
+
+#[tauri::command]
+async fn stop_recording(app: tauri::AppHandle) -> Result<(), String> {
+    trace!("command called : stop_recording");
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let mut state = state_mutex.lock().unwrap();
+
+    match state.recorder.take() {
+        Some(recorder) => {
+            recorder.stop()?;
+        }
+        None => warn!("recorder is None"),
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn record_roast_events(app: tauri::AppHandle, events: Value) -> Result<(), String> {
+    trace!("command called : record_roast_events");
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let state = state_mutex.lock().unwrap();
+
+    match state.recorder.as_ref() {
+        Some(recorder) => recorder.append(JournalEntry::RoastEvents {
+            timestamp: unix_millis(),
+            events,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+async fn recover_session(app: tauri::AppHandle) -> Result<Option<RecoveredSession>, String> {
+    trace!("command called : recover_session");
+
+    recorder::recover(&journal_dir(&app)?)
+}
+
+// called once the user answered whether to recover the interrupted session
+#[tauri::command]
+async fn archive_recovered_session(app: tauri::AppHandle) -> Result<(), String> {
+    trace!("command called : archive_recovered_session");
+
+    let dir = journal_dir(&app)?;
+
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let state = state_mutex.lock().unwrap();
+
+    // a recording started meanwhile already archived it, current.jsonl is its own journal now
+    if state.recorder.is_some() {
+        warn!("recording, interrupted session already archived");
+        return Ok(());
+    }
+
+    recorder::archive(&dir)?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_device_status(app: tauri::AppHandle) -> Vec<DeviceStatus> {
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let state = state_mutex.lock().unwrap();
+    state.device_status.clone()
 }
 
 #[tauri::command]
@@ -114,7 +876,24 @@ async fn get_config(app: tauri::AppHandle) -> Config {
     state.config.clone()
 }
 
+#[tauri::command]
+async fn get_config_errors(app: tauri::AppHandle) -> Vec<ConfigError> {
+    let state_mutex = app.state::<Mutex<RoastCraftState>>();
+    let state = state_mutex.lock().unwrap();
+    state.config_errors.clone()
+}
+
 fn main() {
+    // headless mode never creates the webview, see headless.rs
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "--print-config-schema") {
+        println!("{}", config::schema());
+        std::process::exit(0);
+    }
+    if args.iter().any(|arg| arg == "--headless") {
+        std::process::exit(headless::run(&args));
+    }
+
     const OPEN_FILE: &str = "OPEN_FILE";
     const SAVE_FILE: &str = "SAVE_FILE";
     const LOAD_GHOST: &str = "LOAD_GHOST";
@@ -158,6 +937,26 @@ fn main() {
             button_on_clicked,
             button_off_clicked,
             get_config,
+            get_config_errors,
+            load_config,
+            reload_config,
+            list_configs,
+            migrate_config,
+            save_config,
+            list_machine_presets,
+            create_config_from_preset,
+            get_device_status,
+            list_serial_ports,
+            test_device_connection,
+            set_manual_channel,
+            pid_arm,
+            pid_pause,
+            pid_disarm,
+            start_recording,
+            stop_recording,
+            record_roast_events,
+            recover_session,
+            archive_recovered_session,
         ])
         .plugin(
             tauri_plugin_log::Builder::default()
@@ -168,10 +967,10 @@ fn main() {
         )
         .manage(Mutex::new(RoastCraftState::new()))
         .setup(|app| {
-            // default config file name
-            // in reldase/debug mode, put roastcraft.toml in the same folder with roastcraft.exe
+            // config file is searched in this order, see config/discovery.rs
+            // cli argument, ROASTCRAFT_CONFIG, last used, current dir, executable dir, app config dir
             // in dev mode, put roastcraft.toml in /src-tauri
-            let mut config_file_name = String::from("roastcraft.toml");
+            let mut cli_config: Option<String> = None;
 
             // get cli argument
             match app.get_cli_matches() {
@@ -179,52 +978,54 @@ fn main() {
                 // pnpm tauri dev -- -- --config=../machines/kapok/501_inlet.toml
                 Ok(matches) => {
                     if matches.args.get("config").unwrap().value.is_string() {
-                        config_file_name = matches
+                        cli_config = matches
                             .args
                             .get("config")
                             .unwrap()
                             .value
                             .as_str()
-                            .unwrap()
-                            .to_string();
+                            .map(String::from);
                     }
-                    println!("{}", config_file_name);
                 }
                 Err(_) => {}
             }
 
+            let config_dir = app.path_resolver().app_config_dir();
+            let config_file_name = discovery::find(cli_config.as_deref(), config_dir.as_deref())
+                .to_string_lossy()
+                .to_string();
+            println!("{}", config_file_name);
+
             let mut parse_config_err_msg: String = String::new();
             let mut parse_config_ok = false;
-            let mut toml_content = String::new();
 
             let state_mutex = app.state::<Mutex<RoastCraftState>>();
             let mut state = state_mutex.lock().unwrap();
 
-            match File::open(&config_file_name) {
-                Ok(mut file) => {
-                    match file.read_to_string(&mut toml_content) {
-                        Ok(_) => {
-                            // At this point, `contents` contains the content of the TOML file
-                            match toml::from_str::<Config>(toml_content.as_str()) {
-                                Ok(c) => {
-                                    parse_config_ok = true;
-                                    state.config = c;
-                                }
-                                Err(e) => {
-                                    parse_config_err_msg = format!(
-                                        "Failed to parse {config_file_name} \n{}",
-                                        e.message()
-                                    );
-                                }
-                            }
-                        }
-                        Err(_) => {
-                            parse_config_err_msg = format!("Failed to read {config_file_name}");
-                        }
+            state.config_path = config_file_name.clone();
+
+            match config::load(&config_file_name) {
+                Ok((c, errors)) => {
+                    for e in &errors {
+                        warn!("{}", e);
                     }
+                    parse_config_ok = errors.is_empty();
+                    if !parse_config_ok {
+                        parse_config_err_msg = config::errors_message(&config_file_name, &errors);
+                    }
+                    state.config = c;
+                    state.config_errors = errors;
+                    state.config_modified = modified_time(&config_file_name);
                 }
-                Err(_) => {
-                    parse_config_err_msg = format!("Failed to open {config_file_name}");
+                Err(msg) => {
+                    parse_config_err_msg = msg.clone();
+                    // nothing to acquire from without a config
+                    state.config_errors = vec![ConfigError {
+                        path: config_file_name.clone(),
+                        line: None,
+                        message: msg,
+                        fatal: true,
+                    }];
                 }
             }
 
@@ -236,6 +1037,8 @@ fn main() {
                 tauri::api::dialog::message(Some(&main_window), "RoastCraft", parse_config_err_msg);
             }
 
+            spawn(watch_config(app.handle()));
+
             Ok(())
         })
         .run(tauri::generate_context!())
@@ -0,0 +1,474 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// closed-loop control of a writable manual channel, e.g. gas steered to make BT follow a ghost,
+// see [pid] in config
+//
+// the controller is stepped with every merged sample while the device is on, its outputs are
+// written like set_manual_channel does, see main.rs
+//
+// setpoint time is in seconds on the time axis given at arming, the ghost axis is seconds
+// since CHARGE, ramp/soak segments start at 0 when armed
+
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::config::{self, ManualChannel, Segment};
+use crate::sample::{Quality, Sample};
+
+// samples come a little early or late, an output is due at 90% of sample_time
+const SAMPLE_TIME_TOLERANCE: f64 = 0.9;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PidState {
+    Disarmed,
+    Armed,  // output written every sample_time
+    Paused, // output left to the slider, setpoint time keeps running
+}
+
+// a point of a ghost channel, same as Point in frontend
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProfilePoint {
+    pub timestamp: f64, // seconds
+    pub value: f64,
+}
+
+pub enum Setpoint {
+    Profile(Vec<ProfilePoint>), // sorted by timestamp
+    Segments(Vec<Segment>),
+}
+
+impl Setpoint {
+    // start is the input at the first step, the first ramp starts from it
+    pub fn at(&self, t: f64, start: f64) -> f64 {
+        match self {
+            Setpoint::Profile(points) => interpolate(points, t).unwrap_or(start),
+            Setpoint::Segments(segments) => ramp_soak(segments, t, start),
+        }
+    }
+}
+
+// linear between points, the first and last value are held before and after the profile
+fn interpolate(points: &[ProfilePoint], t: f64) -> Option<f64> {
+    let first = points.first()?;
+    let last = points.last()?;
+    if t <= first.timestamp {
+        return Some(first.value);
+    }
+    if t >= last.timestamp {
+        return Some(last.value);
+    }
+
+    // first point after t, a point at or before t is right in front of it
+    let i = points.partition_point(|p| p.timestamp <= t);
+    let (a, b) = (&points[i - 1], &points[i]);
+    Some(a.value + (b.value - a.value) * (t - a.timestamp) / (b.timestamp - a.timestamp))
+}
+
+// the last target is held after the last segment
+fn ramp_soak(segments: &[Segment], t: f64, start: f64) -> f64 {
+    let mut from = start;
+    let mut begin = 0.0;
+
+    for segment in segments {
+        let ramp = segment.ramp_s as f64;
+        if t < begin + ramp {
+            return from + (segment.target - from) * (t - begin) / ramp;
+        }
+        begin += ramp + segment.soak_s as f64;
+        if t < begin {
+            return segment.target;
+        }
+        from = segment.target;
+    }
+
+    from
+}
+
+// derivative on measurement, a setpoint step does not kick the output, the integral term is
+// kept within the output range and frozen while the output is saturated (anti-windup)
+pub struct Pid {
+    kp: f64,
+    ki: f64,
+    kd: f64,
+    min: f64,
+    max: f64,
+    sample_time_s: f64,
+    integral: f64,            // integral term, ki included
+    last: Option<(f64, f64)>, // time and measurement of the last output
+}
+
+impl Pid {
+    pub fn new(kp: f64, ki: f64, kd: f64, min: f64, max: f64, sample_time_s: f64) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            min,
+            max,
+            sample_time_s,
+            integral: min,
+            last: None,
+        }
+    }
+
+    // start again from output without a bump
+    pub fn reset(&mut self, output: f64) {
+        self.integral = output.clamp(self.min, self.max);
+        self.last = None;
+    }
+
+    // None until sample_time has passed since the last output
+    pub fn update(&mut self, setpoint: f64, measurement: f64, t: f64) -> Option<f64> {
+        let dt = match self.last {
+            Some((last_t, _)) => {
+                let dt = t - last_t;
+                if dt < self.sample_time_s * SAMPLE_TIME_TOLERANCE {
+                    return None;
+                }
+                dt
+            }
+            None => 0.0,
+        };
+
+        let error = setpoint - measurement;
+        let derivative = match self.last {
+            Some((_, last_measurement)) if dt > 0.0 => (measurement - last_measurement) / dt,
+            _ => 0.0,
+        };
+
+        let integral = self.integral + self.ki * error * dt;
+        let unclamped = self.kp * error + integral - self.kd * derivative;
+        let output = unclamped.clamp(self.min, self.max);
+
+        // integrate only when it does not push further into saturation
+        if (unclamped <= self.max || error < 0.0) && (unclamped >= self.min || error > 0.0) {
+            self.integral = integral.clamp(self.min, self.max);
+        }
+
+        self.last = Some((t, measurement));
+        Some(output)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PidStatus {
+    pub state: PidState,
+    pub input: String,  // channel_id
+    pub output: String, // channel_id of the manual channel
+    pub setpoint: Option<f64>,
+    pub measurement: Option<f64>,
+    pub value: Option<f64>, // last output, as read back from the roaster
+    pub elapsed_ms: u64,    // of the sample the last output was computed from
+    pub last_error: Option<String>,
+}
+
+impl PidStatus {
+    pub fn disarmed(pid: &config::Pid) -> Self {
+        Self {
+            state: PidState::Disarmed,
+            input: pid.input(),
+            output: pid.output.clone(),
+            setpoint: None,
+            measurement: None,
+            value: None,
+            elapsed_ms: 0,
+            last_error: None,
+        }
+    }
+}
+
+pub struct Controller {
+    pid: Pid,
+    setpoint: Setpoint,
+    armed_at: Instant,
+    time_at_arm: f64,   // setpoint time when armed
+    start: Option<f64>, // input at the first step
+    pub status: PidStatus,
+}
+
+impl Controller {
+    // a ghost profile has priority over [[pid.segment]], time is its setpoint time at arming,
+    // output is the current value of the manual channel
+    pub fn arm(
+        config: &config::Config,
+        profile: Option<Vec<ProfilePoint>>,
+        time: f64,
+        output: f64,
+    ) -> Result<Controller, String> {
+        let pid_config = config
+            .pid
+            .as_ref()
+            .ok_or("Config has no [pid]".to_string())?;
+        let mc: &ManualChannel = config
+            .manual_channel
+            .iter()
+            .flatten()
+            .find(|mc| mc.channel_id == pid_config.output)
+            .ok_or(format!("Manual channel {} not found", pid_config.output))?;
+
+        let (setpoint, time_at_arm) = match profile.filter(|p| !p.is_empty()) {
+            Some(mut points) => {
+                points.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+                (Setpoint::Profile(points), time)
+            }
+            None => match pid_config.segment.clone().filter(|s| !s.is_empty()) {
+                Some(segments) => (Setpoint::Segments(segments), 0.0),
+                None => return Err("Load a ghost or add [[pid.segment]] to config".to_string()),
+            },
+        };
+
+        let min = pid_config.output_min.unwrap_or(mc.min as f64);
+        let max = pid_config.output_max.unwrap_or(mc.max as f64).max(min);
+        let sample_time = pid_config
+            .sample_time_ms
+            .map(|ms| ms as f64 / 1000.0)
+            .unwrap_or(config.sample_interval().as_secs_f64());
+
+        let mut pid = Pid::new(
+            pid_config.kp,
+            pid_config.ki,
+            pid_config.kd,
+            min,
+            max,
+            sample_time,
+        );
+        pid.reset(output);
+
+        Ok(Controller {
+            pid,
+            setpoint,
+            armed_at: Instant::now(),
+            time_at_arm,
+            start: None,
+            status: PidStatus {
+                state: PidState::Armed,
+                value: Some(output),
+                ..PidStatus::disarmed(pid_config)
+            },
+        })
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.status.state == PidState::Armed
+    }
+
+    pub fn pause(&mut self) {
+        self.status.state = PidState::Paused;
+    }
+
+    // continue from the value the slider was left at
+    pub fn resume(&mut self) {
+        self.pid.reset(self.status.value.unwrap_or(self.pid.min));
+        self.status.state = PidState::Armed;
+    }
+
+    // the output to write when due, the input is held while it is bad or missing
+    pub fn step(&mut self, sample: &Sample) -> Option<f64> {
+        let measurement = sample
+            .channels
+            .iter()
+            .find(|c| c.channel_id == self.status.input && c.quality == Quality::Good)
+            .and_then(|c| c.value)?;
+
+        let t = self.time_at_arm + self.armed_at.elapsed().as_secs_f64();
+        let start = *self.start.get_or_insert(measurement);
+        let setpoint = self.setpoint.at(t, start);
+
+        self.status.setpoint = Some(setpoint);
+        self.status.measurement = Some(measurement);
+
+        if !self.is_armed() {
+            return None;
+        }
+        let output = self.pid.update(setpoint, measurement, t);
+        if output.is_some() {
+            self.status.elapsed_ms = sample.elapsed_ms;
+        }
+        output
+    }
+
+    // result of writing an output, or of setting the manual channel by hand
+    pub fn written(&mut self, result: Result<f64, String>) {
+        match result {
+            Ok(value) => {
+                self.status.value = Some(value);
+                self.status.last_error = None;
+            }
+            Err(e) => self.status.last_error = Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(target: f64, ramp_s: u64, soak_s: u64) -> Segment {
+        Segment {
+            target,
+            ramp_s,
+            soak_s,
+        }
+    }
+
+    fn point(timestamp: f64, value: f64) -> ProfilePoint {
+        ProfilePoint { timestamp, value }
+    }
+
+    #[test]
+    fn interpolate_points() {
+        let points = [point(0.0, 100.0), point(10.0, 200.0), point(20.0, 150.0)];
+
+        // t, setpoint
+        let cases = [
+            (-5.0, 100.0), // first value held before the profile
+            (0.0, 100.0),
+            (5.0, 150.0),
+            (10.0, 200.0),
+            (15.0, 175.0),
+            (20.0, 150.0),
+            (30.0, 150.0), // last value held after the profile
+        ];
+        for (t, expected) in cases {
+            assert_eq!(interpolate(&points, t), Some(expected), "t = {}", t);
+        }
+
+        assert_eq!(interpolate(&[], 5.0), None);
+        assert_eq!(interpolate(&[point(10.0, 42.0)], 0.0), Some(42.0));
+        assert_eq!(interpolate(&[point(10.0, 42.0)], 20.0), Some(42.0));
+    }
+
+    #[test]
+    fn ramp_soak_segments() {
+        // ramp from 100 to 200 in 10 s and hold 5 s, step to 150 and hold 5 s, ramp to 250 in 10 s
+        let segments = [
+            segment(200.0, 10, 5),
+            segment(150.0, 0, 5),
+            segment(250.0, 10, 0),
+        ];
+
+        // t, setpoint
+        let cases = [
+            (0.0, 100.0), // starts from the input at the first step
+            (5.0, 150.0),
+            (10.0, 200.0), // ramp done, soak starts
+            (14.9, 200.0),
+            (15.0, 150.0), // ramp_s = 0 steps at once
+            (19.9, 150.0),
+            (20.0, 150.0), // next ramp starts from the previous target
+            (25.0, 200.0),
+            (30.0, 250.0),
+            (100.0, 250.0), // last target held
+        ];
+        for (t, expected) in cases {
+            let setpoint = ramp_soak(&segments, t, 100.0);
+            assert!(
+                (setpoint - expected).abs() < 1e-9,
+                "t = {} : {}",
+                t,
+                setpoint
+            );
+        }
+
+        assert_eq!(ramp_soak(&[], 10.0, 100.0), 100.0);
+        assert_eq!(ramp_soak(&[segment(180.0, 0, 0)], 0.0, 100.0), 180.0);
+    }
+
+    #[test]
+    fn setpoint_profile_before_segments() {
+        let profile = Setpoint::Profile(vec![point(0.0, 100.0), point(10.0, 200.0)]);
+        assert_eq!(profile.at(5.0, 20.0), 150.0);
+
+        let empty = Setpoint::Profile(Vec::new());
+        assert_eq!(empty.at(5.0, 20.0), 20.0);
+    }
+
+    #[test]
+    fn integral() {
+        let mut pid = Pid::new(0.0, 0.5, 0.0, 0.0, 100.0, 1.0);
+        pid.reset(0.0);
+
+        // t, output with error 10
+        let cases = [(0.0, 0.0), (1.0, 5.0), (2.0, 10.0), (4.0, 20.0)];
+        for (t, expected) in cases {
+            assert_eq!(pid.update(60.0, 50.0, t), Some(expected), "t = {}", t);
+        }
+    }
+
+    #[test]
+    fn anti_windup() {
+        // (reset, setpoint, measurement, limit), output held at the limit, integral frozen
+        let cases = [
+            (0.0, 200.0, 50.0, 10.0),  // max
+            (5.0, 0.0, 50.0, 0.0),     // min
+            (20.0, 200.0, 50.0, 10.0), // reset beyond max is clamped
+        ];
+
+        for (reset, setpoint, measurement, limit) in cases {
+            let mut pid = Pid::new(1.0, 1.0, 0.0, 0.0, 10.0, 1.0);
+            pid.reset(reset);
+            let integral = pid.integral;
+
+            for t in 0..20 {
+                let output = pid.update(setpoint, measurement, t as f64);
+                assert_eq!(output, Some(limit), "t = {}", t);
+            }
+            assert_eq!(pid.integral, integral.clamp(0.0, 10.0));
+        }
+
+        // leaving saturation, the output follows the error right away
+        let mut pid = Pid::new(1.0, 1.0, 0.0, 0.0, 10.0, 1.0);
+        pid.reset(0.0);
+        for t in 0..20 {
+            pid.update(200.0, 50.0, t as f64);
+        }
+        assert_eq!(pid.update(50.0, 48.0, 20.0), Some(4.0));
+    }
+
+    #[test]
+    fn derivative_on_measurement() {
+        // (setpoint at t = 1, measurement at t = 1, output at t = 1)
+        let cases = [
+            (50.0, 10.0, 0.0),  // no change, no derivative
+            (80.0, 10.0, 0.0),  // setpoint step does not kick
+            (50.0, 12.0, -4.0), // rising measurement lowers the output
+            (80.0, 12.0, -4.0),
+            (50.0, 8.0, 4.0),
+        ];
+
+        for (setpoint, measurement, expected) in cases {
+            let mut pid = Pid::new(0.0, 0.0, 2.0, -100.0, 100.0, 1.0);
+            pid.reset(0.0);
+
+            // no derivative without a previous measurement
+            assert_eq!(pid.update(50.0, 10.0, 0.0), Some(0.0));
+            assert_eq!(
+                pid.update(setpoint, measurement, 1.0),
+                Some(expected),
+                "setpoint {} measurement {}",
+                setpoint,
+                measurement
+            );
+        }
+    }
+
+    #[test]
+    fn sample_time() {
+        let mut pid = Pid::new(1.0, 0.0, 0.0, 0.0, 100.0, 2.0);
+        pid.reset(0.0);
+
+        // t, output due
+        let cases = [
+            (0.0, true),
+            (1.0, false),
+            (1.7, false),
+            (1.8, true), // 90% of sample_time
+            (3.0, false),
+            (4.0, true),
+        ];
+        for (t, due) in cases {
+            assert_eq!(pid.update(60.0, 50.0, t).is_some(), due, "t = {}", t);
+        }
+    }
+}
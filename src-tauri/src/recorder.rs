@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::devices::unix_millis;
+use crate::sample::Sample;
+
+// the journal of the running (or interrupted) session, renamed when the session stops
+const CURRENT_JOURNAL: &str = "current.jsonl";
+
+// one line of the journal
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Start { timestamp: u64, brand: String, model: String },
+    Sample(Sample),
+    RoastEvents { timestamp: u64, events: Value }, // latest RoastEvents of frontend
+    Stop { timestamp: u64 },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RecoveredSession {
+    pub started: u64, // unix timestamp in milliseconds
+    pub brand: String,
+    pub model: String,
+    pub samples: Vec<Sample>,
+    pub roast_events: Option<Value>,
+}
+
+// entries are written and synced to disk by a thread of their own, appending does not wait for the disk
+pub struct Recorder {
+    tx: Sender<JournalEntry>,
+    writer: JoinHandle<Result<(), String>>,
+    dir: PathBuf,
+    started: u64,
+}
+
+impl Recorder {
+    // start a new journal in dir, an interrupted journal left in dir is archived first
+    pub fn start(dir: &Path, brand: &str, model: &str) -> Result<Recorder, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?} : {}", dir, e))?;
+
+        let current = dir.join(CURRENT_JOURNAL);
+        if current.exists() {
+            let archived = dir.join(format!("interrupted_{}.jsonl", unix_millis()));
+            warn!("archive interrupted journal to {:?}", archived);
+            fs::rename(&current, &archived)
+                .map_err(|e| format!("Failed to archive {:?} : {}", current, e))?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&current)
+            .map_err(|e| format!("Failed to create {:?} : {}", current, e))?;
+
+        // the first line is written here, a journal that cannot be written fails to start
+        let started = unix_millis();
+        write_entry(
+            &mut file,
+            &JournalEntry::Start {
+                timestamp: started,
+                brand: brand.to_string(),
+                model: model.to_string(),
+            },
+        )?;
+
+        let (tx, rx) = channel();
+        let writer = thread::Builder::new()
+            .name("journal".to_string())
+            .spawn(move || write_journal(file, rx))
+            .map_err(|e| format!("Failed to start journal writer : {}", e))?;
+
+        info!("recording started : {:?}", current);
+        Ok(Recorder {
+            tx,
+            writer,
+            dir: dir.to_path_buf(),
+            started,
+        })
+    }
+
+    // queue an entry for the writer, fails only when the writer is gone
+    pub fn append(&self, entry: JournalEntry) -> Result<(), String> {
+        self.tx
+            .send(entry)
+            .map_err(|_| "Failed to write journal : writer stopped".to_string())
+    }
+
+    // wait for the queued entries to be written, close the journal and keep it
+    // as session_<start time>.jsonl
+    pub fn stop(self) -> Result<PathBuf, String> {
+        let Recorder {
+            tx,
+            writer,
+            dir,
+            started,
+        } = self;
+
+        let _ = tx.send(JournalEntry::Stop {
+            timestamp: unix_millis(),
+        });
+        drop(tx);
+
+        let written = writer
+            .join()
+            .unwrap_or_else(|_| Err("journal writer panicked".to_string()));
+
+        let current = dir.join(CURRENT_JOURNAL);
+        let finished = dir.join(format!("session_{}.jsonl", started));
+        fs::rename(&current, &finished)
+            .map_err(|e| format!("Failed to rename {:?} : {}", current, e))?;
+
+        info!("recording stopped : {:?}", finished);
+        written.map(|_| finished)
+    }
+}
+
+// one json object per line, synced to disk so a crash loses at most the line being written
+fn write_entry(file: &mut File, entry: &JournalEntry) -> Result<(), String> {
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+
+    writeln!(file, "{}", line)
+        .and_then(|_| file.sync_data())
+        .map_err(|e| format!("Failed to write journal : {}", e))
+}
+
+// write entries until the recorder is stopped, a failed entry is logged once and the
+// following ones are still tried, the first error is returned
+fn write_journal(mut file: File, rx: Receiver<JournalEntry>) -> Result<(), String> {
+    let mut result = Ok(());
+    for entry in rx {
+        if let Err(e) = write_entry(&mut file, &entry) {
+            if result.is_ok() {
+                error!("{}", e);
+                result = Err(e);
+            }
+        }
+    }
+    result
+}
+
+// keep the journal of an interrupted session as session_<start time>.jsonl, once the user has
+// been asked about it, whether it was recovered or not
+pub fn archive(dir: &Path) -> Result<Option<PathBuf>, String> {
+    let current = dir.join(CURRENT_JOURNAL);
+    if !current.exists() {
+        return Ok(None);
+    }
+
+    let file = File::open(&current).map_err(|e| format!("Failed to open {:?} : {}", current, e))?;
+
+    // start time from the first line, the journal may be empty when it crashed right away
+    let first = BufReader::new(file)
+        .lines()
+        .next()
+        .and_then(|line| line.ok());
+    let started = match first.map(|line| serde_json::from_str::<JournalEntry>(&line)) {
+        Some(Ok(JournalEntry::Start { timestamp, .. })) => timestamp,
+        _ => unix_millis(),
+    };
+
+    let archived = dir.join(format!("session_{}.jsonl", started));
+    fs::rename(&current, &archived)
+        .map_err(|e| format!("Failed to rename {:?} : {}", current, e))?;
+
+    info!("interrupted session archived : {:?}", archived);
+    Ok(Some(archived))
+}
+
+// read back the journal of an interrupted session, None if the last session stopped normally
+pub fn recover(dir: &Path) -> Result<Option<RecoveredSession>, String> {
+    let current = dir.join(CURRENT_JOURNAL);
+    if !current.exists() {
+        return Ok(None);
+    }
+
+    let file = File::open(&current).map_err(|e| format!("Failed to open {:?} : {}", current, e))?;
+
+    let mut session = RecoveredSession {
+        started: 0,
+        brand: String::new(),
+        model: String::new(),
+        samples: Vec::new(),
+        roast_events: None,
+    };
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read {:?} : {}", current, e))?;
+
+        // the last line may be cut off by the crash
+        match serde_json::from_str::<JournalEntry>(&line) {
+            Ok(JournalEntry::Start {
+                timestamp,
+                brand,
+                model,
+            }) => {
+                session.started = timestamp;
+                session.brand = brand;
+                session.model = model;
+            }
+            Ok(JournalEntry::Sample(sample)) => session.samples.push(sample),
+            Ok(JournalEntry::RoastEvents { events, .. }) => session.roast_events = Some(events),
+            Ok(JournalEntry::Stop { .. }) => {}
+            Err(e) => warn!("skip journal line {} : {}", i + 1, e),
+        }
+    }
+
+    info!(
+        "recovered session started at {} with {} samples",
+        session.started,
+        session.samples.len()
+    );
+    Ok(Some(session))
+}
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    Good,
+    Bad, // channel read failed, value is None
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelReading {
+    pub channel_id: String,
+    pub value: Option<f64>,
+    pub unit: String,
+    pub quality: Quality,
+    pub latency_ms: u64, // time spent reading this channel
+}
+
+impl ChannelReading {
+    pub fn good(channel_id: String, value: f64, unit: String, latency_ms: u64) -> Self {
+        Self {
+            channel_id,
+            value: Some(value),
+            unit,
+            quality: Quality::Good,
+            latency_ms,
+        }
+    }
+
+    pub fn bad(channel_id: String, unit: String, latency_ms: u64) -> Self {
+        Self {
+            channel_id,
+            value: None,
+            unit,
+            quality: Quality::Bad,
+            latency_ms,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Sample {
+    pub elapsed_ms: u64, // monotonic, since recording start to the newest read merged into the sample
+    pub timestamp: u64,  // unix timestamp in milliseconds (UTC), taken when Device::read returned, set by reader
+    pub channels: Vec<ChannelReading>,
+}
+
+impl Sample {
+    pub fn new(channels: Vec<ChannelReading>) -> Self {
+        Self {
+            elapsed_ms: 0,
+            timestamp: 0,
+            channels,
+        }
+    }
+}
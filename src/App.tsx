@@ -1,14 +1,16 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-import { onMount, onCleanup, For, Show } from "solid-js";
+import { onMount, onCleanup, createEffect, For, Show } from "solid-js";
+import { invoke } from "@tauri-apps/api/tauri";
 import { trace, attachConsole } from "tauri-plugin-log-api";
 import { UnlistenFn, listen } from "@tauri-apps/api/event";
+import { ask } from "@tauri-apps/api/dialog";
 
 import MainChart from "./MainChart";
 import { GET, SET, BT, AppStatus, Point, appStateSig, Channel, resetGhost } from "./AppState";
 import { autoDetectChargeDrop, calculatePhases, calculateRor, detectAlarm, findDryEnd, findRorOutlier, findTurningPoint } from "./calculate";
 import SecondaryChart from "./SecondaryChart";
-import { openFile, loadGhost, saveFile } from "./fileUtil";
+import { openFile, loadGhost, saveFile, recoverSession } from "./fileUtil";
 import DashboardPanel, { buttonOffClicked, buttonOnClicked, buttonResetClicked, buttonStartClicked, handleCharge, handleDrop, handleDryEnd, handleFCEnd, handleFCStart, handleSCEnd, handleSCStart } from "./DashboardPanel";
 import NotesPanel from "./NotesPanel";
 import SettingsPanel from "./SettingsPanel";
@@ -37,7 +39,10 @@ function App() {
 
     let detach: UnlistenFn;
     let unlisten_reader: UnlistenFn;
+    let unlisten_device_status: UnlistenFn;
     let unlisten_menu_event_listener: UnlistenFn;
+    let unlisten_config_changed: UnlistenFn;
+    let unlisten_pid_status: UnlistenFn;
 
     onMount(async () => {
 
@@ -49,17 +54,26 @@ function App() {
         unlisten_reader = await listen("read_channels", (event: any) => {
             trace("event \"read_channels\" catched :" + JSON.stringify(event.payload));
 
+            // payload is a Sample, see sample.rs in backend
+            // a channel with bad quality keeps its previous value
+            let values: { [id: string]: number } = {};
+            channelArr().forEach((c) => values[c.id] = c.currentDataSig[GET]());
+            event.payload.channels
+                .filter((c: any) => c.quality == "good")
+                .forEach((c: any) => values[c.channel_id] = c.value);
+
             // update current channels and ror
             let i;
             for (i = 0; i < channelIdList.length; i++) {
 
-                channelArr()[i].currentDataSig[SET](Number(event.payload[channelIdList[i]]));
+                channelArr()[i].currentDataSig[SET](Number(values[channelIdList[i]]));
 
                 /* calculate ROR start */
                 channelArr()[i].dataWindowArr.push(
                     {
-                        value: Number(event.payload[channelIdList[i]]),
-                        system_time: new Date().getTime()
+                        value: Number(values[channelIdList[i]]),
+                        // timestamp taken by backend right after reading the device
+                        system_time: event.payload.timestamp
                     }
                 );
 
@@ -79,10 +93,10 @@ function App() {
                 );
                 /* calculate ROR end */
 
-                // write into history data
+                // write into history data, on the time axis of the backend
                 if (status() == AppStatus.RECORDING) {
                     channelArr()[i].setDataArr(
-                        [...channelArr()[i].dataArr(), new Point(timer(), event.payload[channelIdList[i]])]
+                        [...channelArr()[i].dataArr(), new Point(event.payload.elapsed_ms / 1000, values[channelIdList[i]])]
                     )
                 }
             }
@@ -109,6 +123,58 @@ function App() {
 
         });
 
+        // event listener
+        unlisten_device_status = await listen("device_status", (event: any) => {
+            trace("event \"device_status\" catched :" + JSON.stringify(event.payload));
+
+            let statusArr = appState().deviceStatusSig[GET]();
+            let previous = statusArr.find((s) => s.device == event.payload.device);
+            appState().deviceStatusSig[SET](previous == undefined
+                ? [...statusArr, event.payload]
+                : statusArr.map((s) => s.device == event.payload.device ? event.payload : s));
+
+            // only log when the error changes, not on every failed read
+            if (event.payload.last_error != null && event.payload.last_error != previous?.last_error) {
+                setLogArr([...logArr(), event.payload.device + " error : " + event.payload.last_error]);
+            }
+        });
+
+        // event listener
+        unlisten_pid_status = await listen("pid_status", (event: any) => {
+            trace("event \"pid_status\" catched :" + JSON.stringify(event.payload));
+
+            let previous = appState().pidStatusSig[GET]();
+            appState().pidStatusSig[SET](event.payload);
+
+            // outputs written by the controller move the slider and are recorded like a hand set value
+            let mc = manualChannelArr().find((mc) => mc.id == event.payload.output);
+            if (mc != undefined && event.payload.state == "armed" && event.payload.value != null
+                && event.payload.value != mc.currentDataSig[GET]()) {
+                mc.currentDataSig[SET](event.payload.value);
+                mc.setDataArr([...mc.dataArr(), new Point(event.payload.elapsed_ms / 1000, event.payload.value)]);
+            }
+
+            if (event.payload.last_error != null && event.payload.last_error != previous?.last_error) {
+                setLogArr([...logArr(), "pid error : " + event.payload.last_error]);
+            }
+        });
+
+        // event listener
+        // channels are built from config once, reload the page to rebuild them,
+        // a roast or ghost on screen is only thrown away when the user agrees
+        unlisten_config_changed = await listen("config_changed", async (event: any) => {
+            trace("event \"config_changed\" catched :" + JSON.stringify(event.payload));
+
+            let hasRoast = channelArr().some((c) => c.dataArr().length > 0);
+            let hasGhost = appState().ghostSig[GET]().channelArr.some((c) => c.dataArr.length > 0);
+            if ((hasRoast || hasGhost)
+                && !await ask("Config changed, reloading clears the roast and ghost on screen. Reload now ?", "RoastCraft")) {
+                setLogArr([...logArr(), "config changed, channels are updated on next start"]);
+                return;
+            }
+            window.location.reload();
+        });
+
         // event listener
         unlisten_menu_event_listener = await listen("menu_event", (event) => {
             switch (event.payload) {
@@ -182,12 +248,26 @@ function App() {
 
         setLogArr([...logArr(), "RoastCraft is ready"]);
 
+        await recoverSession();
+
+    });
+
+    // journal roast events in backend, so an interrupted roast can be recovered
+    createEffect(() => {
+        let events = roastEvents();
+        if (status() == AppStatus.RECORDING) {
+            invoke("record_roast_events", { events: events })
+                .catch((e) => setLogArr([...logArr(), "failed to journal roast events : " + e]));
+        }
     });
 
     onCleanup(() => {
         detach();
         unlisten_reader();
+        unlisten_device_status();
         unlisten_menu_event_listener();
+        unlisten_config_changed();
+        unlisten_pid_status();
     })
 
     function initResizerFn() {
@@ -314,12 +314,63 @@ export enum AppStatus {
     RECORDING = 'RECORDING',
 }
 
+// mirrors devices::DeviceStatus in backend
+export interface DeviceStatus {
+    device: string, // name of the device, "serial", "tcp" or [[device]] name
+    state: "connected" | "reading" | "degraded" | "disconnected",
+    last_error: string | null,
+    last_error_kind: string | null,
+    consecutive_failures: number,
+    last_success: number | null, // unix timestamp in milliseconds
+}
+
+// mirrors pid::PidStatus in backend
+export interface PidStatus {
+    state: "disarmed" | "armed" | "paused",
+    input: string,  // channel_id followed
+    output: string, // channel_id of the manual channel written
+    setpoint: number | null,
+    measurement: number | null,
+    value: number | null, // last output, as read back from the roaster
+    elapsed_ms: number,   // of the sample the last output was computed from
+    last_error: string | null,
+}
+
+// mirrors devices::serial::PortInfo in backend
+export interface PortInfo {
+    port: string,
+    port_type: "usb" | "pci" | "bluetooth" | "unknown",
+    vid: number | null,
+    pid: number | null,
+    manufacturer: string | null,
+    product: string | null,
+    serial_number: string | null,
+}
+
+// mirrors config::discovery::ConfigFile in backend
+export interface ConfigFile {
+    path: string,
+    brand: string | null,
+    model: string | null,
+    last_used: boolean,
+}
+
+// mirrors config::presets::MachinePreset in backend
+export interface MachinePreset {
+    brand: string,
+    model: string,
+    file: string,
+    connection: "serial" | "tcp",
+    channels: Array<string>,
+}
+
 export class ManualChannel {
     id: string;
     min: number;
     max: number;
     step: number;
     defaultValue: number;
+    writable: boolean;                    // bound to a modbus register, see set_manual_channel
     currentDataSig: Signal<number>;
     dataArr: Accessor<Point[]>;           // history records
     setDataArr: Setter<Point[]>;          // history records
@@ -330,6 +381,7 @@ export class ManualChannel {
         max: number,
         step: number,
         defaultValue: number,
+        writable: boolean,
         currentDataSig: Signal<number>,
         dataArrSig: Signal<Point[]>) {
         this.id = id;
@@ -337,6 +389,7 @@ export class ManualChannel {
         this.max = max;
         this.step = step;
         this.defaultValue = defaultValue;
+        this.writable = writable;
         this.currentDataSig = currentDataSig;
         this.dataArr = dataArrSig[GET];
         this.setDataArr = dataArrSig[SET];
@@ -424,6 +477,18 @@ function init_ghostSig() {
     return new Ghost(timeDelta, channelArr, manualChannelArr, {} as RoastEvents);
 }
 
+// channel configs of [serial] or [tcp], serial has priority over tcp and modbus over
+// ta612c / http, same as backend
+function deviceChannels(serial: any, tcp: any): any[] {
+    if (serial != null) {
+        return serial.modbus != null ? serial.modbus.slave : serial.ta612c?.channel ?? [];
+    }
+    if (tcp != null) {
+        return tcp.modbus != null ? tcp.modbus.slave : tcp.http?.channel ?? [];
+    }
+    return [];
+}
+
 async function init_appStateSig() {
     // get config from backend
     let config: any;
@@ -432,49 +497,10 @@ async function init_appStateSig() {
     console.log("config");
     console.log(config);
 
-    let channelArr: Channel[];
-    if (config.serial != null) {
-        if (config.serial.modbus != null) {
-            channelArr = config.serial.modbus.slave.map((s: any) =>
-                new Channel(
-                    s.channel_id,    // id
-                    s.label,         // label 
-                    s.color,         // color
-                    s.ror_color,     // ror_color
-                    createSignal(0), //currentDataSig
-                    createSignal(0), //currentRorSig
-                    [], //data_window
-                    createSignal(new Array<Point>()), // dataSig
-                    createSignal(new Array<Point>()), // rorSig
-                    createSignal(new Array<Point>()), // rorOutlierSig
-                    createSignal(new Array<Point>()), // rorFilteredSig
-                    createSignal(new Array<Point>()), // rorConvolveSig
-                    createSignal(0),
-                    createSignal(-100),
-                )
-            );
-        } else {
-            channelArr = config.serial.ta612c.channel.map((s: any) =>
-                new Channel(
-                    s.channel_id,    // id
-                    s.label,         // label 
-                    s.color,         // color
-                    s.ror_color,     // ror_color
-                    createSignal(0), //currentDataSig
-                    createSignal(0), //currentRorSig
-                    [], //data_window
-                    createSignal(new Array<Point>()), // dataSig
-                    createSignal(new Array<Point>()), // rorSig
-                    createSignal(new Array<Point>()), // rorOutlierSig
-                    createSignal(new Array<Point>()), // rorFilteredSig
-                    createSignal(new Array<Point>()), // rorConvolveSig
-                    createSignal(0),
-                    createSignal(-100),
-                )
-            );
-        }
-    } else {
-        channelArr = config.tcp.http.channel.map((s: any) =>
+    // channels of all devices, in the same order as Config::channel_ids() in backend
+    let channelArr: Channel[] = [config, ...(config.device ?? [])]
+        .flatMap((d: any) => deviceChannels(d.serial, d.tcp))
+        .map((s: any) =>
             new Channel(
                 s.channel_id,    // id
                 s.label,         // label 
@@ -492,7 +518,6 @@ async function init_appStateSig() {
                 createSignal(-100),
             )
         );
-    }
 
     let manualChannelArr: Array<ManualChannel> = new Array<ManualChannel>();
 
@@ -504,6 +529,7 @@ async function init_appStateSig() {
                 c.max,
                 c.step,
                 c.default_value,
+                c.modbus != null,
                 createSignal(c.default_value),
                 createSignal([new Point(0, c.default_value)])
             )
@@ -512,10 +538,21 @@ async function init_appStateSig() {
 
     return {
         statusSig: createSignal(AppStatus.OFF),
+        deviceStatusSig: createSignal(new Array<DeviceStatus>()), // one per device
         timerSig: createSignal(0),
         timeDeltaSig: createSignal(0),
         channelArrSig: createSignal(channelArr),
         manualChannelArrSig: createSignal(manualChannelArr),
+        pidStatusSig: createSignal(config.pid == null ? null : {
+            state: "disarmed",
+            input: config.pid.input ?? BT,
+            output: config.pid.output,
+            setpoint: null,
+            measurement: null,
+            value: null,
+            elapsed_ms: 0,
+            last_error: null,
+        } as PidStatus | null), // null without [pid] in config
         logArrSig: createSignal(new Array<string>()),
         roastEventsSig: createSignal({
             CHARGE: undefined,
@@ -7,10 +7,12 @@ import { calculatePhases, timestamp_format } from "./calculate";
 import WorkerFactory from "./WorkerFactory";
 import timerWorker from "./timer.worker";
 import RangeInput from "./RangeInput";
+import PidControl from "./PidControl";
 import PhaseChart from "./PhaseChart";
 
 const [appState, _setAppState] = appStateSig;
 const [status, setStatus] = appState().statusSig;
+const [deviceStatus, _setDeviceStatus] = appState().deviceStatusSig;
 const [timer, setTimer] = appState().timerSig;
 const [channelArr, _setChannelArr] = appState().channelArrSig;
 const [logArr, setLogArr] = appState().logArrSig;
@@ -57,12 +59,26 @@ export async function buttonOnClicked() {
     // implicit reset data
     resetChannels();
 
-    await invoke("button_on_clicked");
+    try {
+        await invoke("button_on_clicked");
+    } catch (e) {
+        setLogArr([...logArr(), "failed to start reading channels : " + e]);
+        return;
+    }
     setStatus(AppStatus.ON);
     setLogArr([...logArr(), "start reading channels..."]);
 }
 
 export async function buttonOffClicked() {
+    if (status() == AppStatus.RECORDING) {
+        try {
+            // close the journal before the reader, no sample is journaled after stop
+            await invoke("stop_recording");
+        } catch (e) {
+            setLogArr([...logArr(), "failed to stop journal : " + e]);
+        }
+    }
+
     await invoke("button_off_clicked");
     if (timer_worker) {
         timer_worker.terminate()
@@ -74,6 +90,13 @@ export async function buttonOffClicked() {
 }
 
 export async function buttonStartClicked() {
+    try {
+        // backend journals every sample from now on
+        await invoke("start_recording");
+    } catch (e) {
+        setLogArr([...logArr(), "failed to start journal : " + e]);
+    }
+
     timer_worker = new WorkerFactory(timerWorker) as Worker;
     timer_worker.postMessage(1000);
     timer_worker.onmessage = (event: any) => {
@@ -101,6 +124,19 @@ export default function DashboardPanel() {
                         {timestamp_format(timer() + appState().timeDeltaSig[GET]())}
                     </Show>
                 </div>
+                {/* device connection indicators */}
+                <For each={deviceStatus()}>{(s) =>
+                    <div class={`badge self-center ${s.state == "reading" ? "badge-success"
+                        : s.state == "degraded" ? "badge-warning"
+                            : s.state == "connected" ? "badge-info"
+                                : "badge-ghost"}`}
+                        title={s.device + (s.last_success != null
+                            ? ", last read : " + new Date(s.last_success as number).toLocaleTimeString()
+                            : ", no successful read yet")}
+                    >
+                        {deviceStatus().length > 1 ? s.device + " " + s.state : s.state}
+                    </div>
+                }</For>
                 <Show when={status() == AppStatus.OFF}>
                     <button class="ml-auto btn btn-accent rounded relative w-20"
                         onClick={buttonResetClicked}
@@ -146,6 +182,14 @@ export default function DashboardPanel() {
                 </Show>
 
             </div>
+            {/* device failure banner */}
+            <Show when={status() != AppStatus.OFF}>
+                <For each={deviceStatus().filter((s) => s.consecutive_failures > 0)}>{(s) =>
+                    <div class="alert alert-warning rounded p-1 text-sm">
+                        {s.device} : {s.consecutive_failures} failed read(s) : {s.last_error}
+                    </div>
+                }</For>
+            </Show>
             {/* channels */}
             <div class="flex flex-wrap gap-1">
                 {/* BT */}
@@ -276,6 +320,9 @@ export default function DashboardPanel() {
                     <RangeInput channel_id={mc.id}></RangeInput>
                 )}
             </For>
+            <Show when={appState().pidStatusSig[GET]() != null}>
+                <PidControl></PidControl>
+            </Show>
             <div class="flex flex-wrap gap-1">
                 <select
                     class="select select-bordered select-sm"
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+import { Show } from "solid-js";
+import { invoke } from "@tauri-apps/api/tauri";
+import { GET, appStateSig, AppStatus, PidStatus } from "./AppState";
+
+// arm, pause and disarm the controller of [pid] in config, see pid.rs in backend
+export default function PidControl() {
+
+    const [appState, _setAppState] = appStateSig;
+    const [status, _setStatus] = appState().statusSig;
+    const [timer, _setTimer] = appState().timerSig;
+    const [manualChannelArr, _setManualChannelArr] = appState().manualChannelArrSig;
+    const [logArr, setLogArr] = appState().logArrSig;
+    const [ghost, _setGhost] = appState().ghostSig;
+    const pidStatus = appState().pidStatusSig[GET] as () => PidStatus;
+
+    // the ghost of the input channel is followed in seconds since CHARGE,
+    // without ghost the [[pid.segment]] of config start now
+    async function arm() {
+        let ghostChannel = ghost().channelArr.find((c) => c.id == pidStatus().input);
+        let profile = ghostChannel == undefined || ghostChannel.dataArr.length == 0
+            ? null
+            : ghostChannel.dataArr.map((p) => ({ timestamp: p.timestamp + ghost().timeDelta, value: p.value }));
+        let mc = manualChannelArr().find((mc) => mc.id == pidStatus().output);
+
+        await invoke("pid_arm", {
+            profile: profile,
+            time: timer() + appState().timeDeltaSig[GET](),
+            output: mc?.currentDataSig[GET]() ?? 0,
+        }).catch((e) => setLogArr([...logArr(), "failed to arm pid : " + e]));
+    }
+
+    async function pause(paused: boolean) {
+        await invoke("pid_pause", { paused: paused })
+            .catch((e) => setLogArr([...logArr(), "failed to pause pid : " + e]));
+    }
+
+    async function disarm() {
+        await invoke("pid_disarm");
+    }
+
+    return (
+        <div class="flex flex-wrap items-center gap-1 pb-2">
+            <span class="label-text">
+                PID {pidStatus().output} → {pidStatus().input}
+                <Show when={pidStatus().state != "disarmed" && pidStatus().setpoint != null}>
+                    {" "}SP {pidStatus().setpoint?.toFixed(1)}
+                </Show>
+            </span>
+            <Show when={pidStatus().state == "disarmed"}>
+                <button class="ml-auto btn btn-sm btn-accent rounded"
+                    onClick={arm}
+                    disabled={status() == AppStatus.OFF}
+                    tabindex="-1"
+                >
+                    ARM
+                </button>
+            </Show>
+            <Show when={pidStatus().state == "armed"}>
+                <button class="ml-auto btn btn-sm btn-accent rounded"
+                    onClick={() => pause(true)}
+                    tabindex="-1"
+                >
+                    PAUSE
+                </button>
+            </Show>
+            <Show when={pidStatus().state == "paused"}>
+                <button class="ml-auto btn btn-sm btn-accent rounded"
+                    onClick={() => pause(false)}
+                    tabindex="-1"
+                >
+                    RESUME
+                </button>
+            </Show>
+            <Show when={pidStatus().state != "disarmed"}>
+                <button class="btn btn-sm btn-accent rounded"
+                    onClick={disarm}
+                    tabindex="-1"
+                >
+                    DISARM
+                </button>
+            </Show>
+        </div>
+    );
+}
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 import { For, } from "solid-js";
+import { invoke } from "@tauri-apps/api/tauri";
 import { SET, Point, appStateSig, ManualChannel, AppStatus, GET } from "./AppState";
 
 export default function RangeInput(props: { channel_id: string }) {
@@ -9,9 +10,14 @@ export default function RangeInput(props: { channel_id: string }) {
     const [status, _setStatus] = appState().statusSig;
     const [timer, _setTimer] = appState().timerSig;
     const [manualChannelArr, _setManualChannelArr] = appState().manualChannelArrSig;
+    const [logArr, setLogArr] = appState().logArrSig;
+    const [pidStatus, _setPidStatus] = appState().pidStatusSig;
 
     let mc = manualChannelArr().find(mc => mc.id == props.channel_id) as ManualChannel;
 
+    // the controller writes this channel while armed, see PidControl
+    const controlled = () => pidStatus()?.state == "armed" && pidStatus()?.output == mc.id;
+
     let min = mc.min;
     let max = mc.max;
     let step = mc.step;
@@ -35,6 +41,10 @@ export default function RangeInput(props: { channel_id: string }) {
 
     function handlePipClick(pip: number) {
 
+        if (controlled()) {
+            return;
+        }
+
         let value = pip;
 
         mc.currentDataSig[SET](Number(value));
@@ -42,6 +52,26 @@ export default function RangeInput(props: { channel_id: string }) {
             [...mc.dataArr(), new Point(timer(), Number(value))]
         );
 
+        writeValue(value);
+    }
+
+    // send the value to the roaster once the slider is released, the value read back is recorded
+    // when the roaster clamped it
+    async function writeValue(value: number) {
+        if (!mc.writable) {
+            return;
+        }
+
+        await invoke("set_manual_channel", { channelId: mc.id, value: value })
+            .then((readBack) => {
+                if (readBack != value) {
+                    mc.currentDataSig[SET](readBack as number);
+                    mc.setDataArr(
+                        [...mc.dataArr(), new Point(timer(), readBack as number)]
+                    );
+                }
+            })
+            .catch((e) => setLogArr([...logArr(), "failed to set " + mc.id + " : " + e]));
     }
 
     return (
@@ -57,7 +87,8 @@ export default function RangeInput(props: { channel_id: string }) {
                 value={mc.currentDataSig[GET]()}
                 step={step}
                 onInput={handleInput}
-                disabled={status() == AppStatus.OFF}
+                onChange={event => writeValue(Number((event.target as HTMLInputElement).value))}
+                disabled={status() == AppStatus.OFF || controlled()}
                 onclick={event => (event.target as HTMLInputElement).blur()}
             />
             <div class="w-full flex justify-between text-xs px-2 pb-4 relative">
@@ -1,11 +1,96 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-import { createEffect } from "solid-js";
-import { SET, appStateSig } from "./AppState";
+import { invoke } from "@tauri-apps/api/tauri";
+import { For, Show, createEffect, createSignal, onMount } from "solid-js";
+import { GET, SET, AppStatus, ConfigFile, MachinePreset, PortInfo, appStateSig } from "./AppState";
+import { createConfigFromPreset, loadConfig, reloadConfig } from "./fileUtil";
+
+function portLabel(p: PortInfo): string {
+    let label = p.port;
+    if (p.vid != null && p.pid != null) {
+        label += " [" + p.vid.toString(16).padStart(4, "0") + ":" + p.pid.toString(16).padStart(4, "0") + "]";
+    }
+    if (p.product != null || p.manufacturer != null) {
+        label += " " + (p.product ?? p.manufacturer);
+    }
+    if (p.serial_number != null) {
+        label += " #" + p.serial_number;
+    }
+    return label;
+}
 
 export default function SettingsPanel() {
 
     const [appState, _setAppState] = appStateSig;
+    const [ports, setPorts] = createSignal(new Array<PortInfo>());
+    const [selectedPort, setSelectedPort] = createSignal("");
+    const [testResult, setTestResult] = createSignal("");
+    const [presets, setPresets] = createSignal(new Array<MachinePreset>());
+    const [selectedPreset, setSelectedPreset] = createSignal(-1);
+    const [presetPort, setPresetPort] = createSignal("");
+
+    const [configs, setConfigs] = createSignal(new Array<ConfigFile>());
+    const [migrated, setMigrated] = createSignal(false);
+
+    // editable copy of backend config, written by save_config
+    const [config, setConfig] = createSignal<any>(null);
+    const [alarmsText, setAlarmsText] = createSignal("");
+
+    async function saveConfig() {
+        let edited = structuredClone(config());
+        edited.alarms = alarmsText().split(",").map((a) => a.trim()).filter((a) => a != "").map(Number);
+        if (edited.alarms.some((a: number) => isNaN(a))) {
+            setTestResult("alarms must be numbers separated by commas");
+            return;
+        }
+        try {
+            // backend emits config_changed on success, the page reloads
+            await invoke("save_config", { config: edited });
+        } catch (e) {
+            setTestResult("failed to save config : " + e);
+        }
+    }
+
+    onMount(async () => {
+        setPresets(await invoke("list_machine_presets") as Array<MachinePreset>);
+        let configErrors = await invoke("get_config_errors") as Array<any>;
+        setMigrated(configErrors.some((e) => e.message.startsWith("migrated")));
+        let c: any = await invoke("get_config");
+        setConfig(c);
+        setAlarmsText(c.alarms.join(", "));
+        try {
+            setConfigs(await invoke("list_configs") as Array<ConfigFile>);
+        } catch (e) {
+            setTestResult("failed to list configs : " + e);
+        }
+    });
+
+    async function createConfig() {
+        let preset = presets()[selectedPreset()];
+        if (preset != undefined) {
+            await createConfigFromPreset(preset.brand, preset.model, presetPort());
+        }
+    }
+
+    async function scanPorts() {
+        try {
+            setPorts(await invoke("list_serial_ports") as Array<PortInfo>);
+        } catch (e) {
+            setTestResult("failed to list serial ports : " + e);
+        }
+    }
+
+    async function testConnection() {
+        setTestResult("testing...");
+        try {
+            let sample: any = await invoke("test_device_connection", { port: selectedPort() == "" ? null : selectedPort() });
+            setTestResult(sample.channels.map((c: any) =>
+                c.channel_id + " : " + (c.quality == "good" ? c.value + " " + c.unit : "bad")
+            ).join(", "));
+        } catch (e) {
+            setTestResult("failed : " + e);
+        }
+    }
 
     createEffect(() => {
 
@@ -25,6 +110,113 @@ export default function SettingsPanel() {
                     appState().toggleShowRorOutlierSig[SET](Boolean(e.currentTarget.checked));
                 }} />
             </label>
+
+            <div class="divider m-0"></div>
+
+            <div class="flex flex-row gap-1">
+                <button class="btn btn-xs flex-grow" onClick={loadConfig}
+                    disabled={appState().statusSig[GET]() == AppStatus.RECORDING}>
+                    load config
+                </button>
+                <button class="btn btn-xs flex-grow" onClick={reloadConfig}
+                    disabled={appState().statusSig[GET]() == AppStatus.RECORDING}>
+                    reload config
+                </button>
+            </div>
+
+            <Show when={config() != null}>
+                <label class="label py-1">
+                    <span class="label-text mr-1">sample interval (ms)</span>
+                    <input type="number" class="input input-bordered input-xs w-24" min="100" max="60000" step="100"
+                        value={config().sample_interval_ms ?? 2000}
+                        onChange={(e) => setConfig({ ...config(), sample_interval_ms: Number(e.currentTarget.value) })} />
+                </label>
+                <Show when={config().serial != null}>
+                    <label class="label py-1">
+                        <span class="label-text mr-1">serial port</span>
+                        <input type="text" class="input input-bordered input-xs w-24"
+                            value={config().serial.port}
+                            onChange={(e) => setConfig({ ...config(), serial: { ...config().serial, port: e.currentTarget.value } })} />
+                    </label>
+                </Show>
+                <Show when={config().serial == null && config().tcp != null}>
+                    <label class="label py-1">
+                        <span class="label-text mr-1">ip</span>
+                        <input type="text" class="input input-bordered input-xs w-24"
+                            value={config().tcp.ip}
+                            onChange={(e) => setConfig({ ...config(), tcp: { ...config().tcp, ip: e.currentTarget.value } })} />
+                    </label>
+                </Show>
+                <label class="label py-1">
+                    <span class="label-text mr-1">alarms</span>
+                    <input type="text" class="input input-bordered input-xs w-24"
+                        value={alarmsText()}
+                        onChange={(e) => setAlarmsText(e.currentTarget.value)} />
+                </label>
+                <button class="btn btn-xs"
+                    disabled={appState().statusSig[GET]() == AppStatus.RECORDING}
+                    onClick={saveConfig}>
+                    save config
+                </button>
+            </Show>
+
+            <Show when={migrated()}>
+                <button class="btn btn-xs btn-warning mt-1"
+                    disabled={appState().statusSig[GET]() == AppStatus.RECORDING}
+                    onClick={async () => {
+                        await invoke("migrate_config").catch((err) => setTestResult("failed to upgrade config : " + err));
+                    }}>
+                    upgrade config file
+                </button>
+            </Show>
+            <Show when={configs().length > 0}>
+                <select class="select select-bordered select-xs mt-1"
+                    disabled={appState().statusSig[GET]() == AppStatus.RECORDING}
+                    onChange={async (e) => {
+                        if (e.currentTarget.value != "") {
+                            await invoke("load_config", { path: e.currentTarget.value }).catch((err) => setTestResult("failed to load config : " + err));
+                        }
+                    }}>
+                    <option value="">saved configs</option>
+                    <For each={configs()}>
+                        {(c) => <option value={c.path}>{(c.brand ?? "?") + " " + (c.model ?? "?") + (c.last_used ? " *" : "") + " - " + c.path}</option>}
+                    </For>
+                </select>
+            </Show>
+
+            <div class="flex flex-row gap-1 mt-1">
+                <select class="select select-bordered select-xs flex-grow" onChange={(e) => setSelectedPreset(Number(e.currentTarget.value))}>
+                    <option value="-1">machine preset</option>
+                    <For each={presets()}>
+                        {(p, i) => <option value={i()}>{p.brand + " " + p.model + " (" + p.connection + ")"}</option>}
+                    </For>
+                </select>
+                <button class="btn btn-xs" onClick={createConfig}
+                    disabled={selectedPreset() < 0 || appState().statusSig[GET]() == AppStatus.RECORDING}>
+                    create
+                </button>
+            </div>
+            <input type="text" class="input input-bordered input-xs mt-1" placeholder="port, or ip:port for tcp (optional)"
+                value={presetPort()} onChange={(e) => setPresetPort(e.currentTarget.value)} />
+
+            <div class="divider m-0"></div>
+
+            <div class="flex flex-row gap-1">
+                <select class="select select-bordered select-xs flex-grow" onChange={(e) => setSelectedPort(e.currentTarget.value)}>
+                    <option value="">port of config</option>
+                    <For each={ports()}>
+                        {(p) => <option value={p.port}>{portLabel(p)}</option>}
+                    </For>
+                </select>
+                <button class="btn btn-xs" onClick={scanPorts}>scan</button>
+            </div>
+            <button class="btn btn-xs mt-1" onClick={testConnection}
+                disabled={appState().statusSig[GET]() != AppStatus.OFF}>
+                test connection
+            </button>
+            <Show when={testResult() != ""}>
+                <p class="text-xs mt-1 break-words">{testResult()}</p>
+            </Show>
         </div>
     )
-}
\ No newline at end of file
+}
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-import { open, save } from '@tauri-apps/api/dialog';
+import { ask, open, save } from '@tauri-apps/api/dialog';
+import { invoke } from "@tauri-apps/api/tauri";
 import { readTextFile, writeTextFile } from "@tauri-apps/api/fs";
 import { GET, SET, Point, appStateSig, Channel, Ghost, GhostChannel, BT, init_flavorWheel } from "./AppState";
 import { calculatePhases, calculateRor, findRorOutlier } from './calculate';
@@ -111,6 +112,71 @@ export async function openFile() {
     }
 }
 
+// restore the roast of an interrupted session from the backend journal
+export async function recoverSession() {
+    const [appState, _setAppState] = appStateSig;
+    const [channelArr, _setChannelArr] = appState().channelArrSig;
+    const [roastEvents, _setRoastEvents] = appState().roastEventsSig;
+    const [logArr, setLogArr] = appState().logArrSig;
+    const [_dryingPhase, setDryingPhase] = appState().dryingPhaseSig;
+    const [_maillardPhase, setMaillardPhase] = appState().maillardPhaseSig;
+    const [_developPhase, setDevelopPhase] = appState().developPhaseSig;
+    const bt = channelArr().find(c => c.id == BT) as Channel;
+
+    try {
+        let session: any = await invoke("recover_session");
+        if (session == null) {
+            return;
+        }
+
+        let started = new Date(session.started).toLocaleString();
+        let confirmed = session.samples.length > 0
+            && await ask("Recover the interrupted roast started at " + started + " ?", "RoastCraft");
+
+        // answered, the journal is kept as a finished session and not offered again
+        await invoke("archive_recovered_session");
+        if (!confirmed) {
+            return;
+        }
+
+        channelArr().forEach((channel) => {
+            let dataArr = new Array<Point>();
+            session.samples.forEach((sample: any) => {
+                let reading = sample.channels.find((c: any) => c.channel_id == channel.id && c.quality == "good");
+                if (reading != undefined) {
+                    dataArr.push(new Point(sample.elapsed_ms / 1000, reading.value));
+                }
+            });
+            channel.setDataArr(dataArr);
+        });
+
+        if (session.roast_events != null) {
+            appState().roastEventsSig[SET](session.roast_events);
+        }
+
+        let chargeEvent = appState().roastEventsSig[GET]().CHARGE;
+        if (chargeEvent != undefined) {
+            appState().timeDeltaSig[SET](- chargeEvent.timestamp);
+        }
+
+        calculateRor(bt, roastEvents());
+        findRorOutlier(bt);
+
+        let lastBTPoint = bt.dataArr()[bt.dataArr().length - 1];
+        if (lastBTPoint != undefined) {
+            appState().timerSig[SET](lastBTPoint.timestamp);
+            let result = calculatePhases(lastBTPoint.timestamp, lastBTPoint.value, roastEvents());
+            setDryingPhase(result.dry);
+            setMaillardPhase(result.mai);
+            setDevelopPhase(result.dev);
+        }
+
+        setLogArr([...logArr(), "recovered interrupted roast started at " + started]);
+    } catch (e) {
+        setLogArr([...logArr(), "failed to recover interrupted roast : " + e]);
+    }
+}
+
 export async function loadGreenBeanInfo() {
     const [appState, _setAppState] = appStateSig;
 
@@ -298,4 +364,51 @@ export async function saveFile() {
     } catch (e) {
         console.log(e);
     }
-}
\ No newline at end of file
+}
+
+// switch to another config file, backend emits config_changed on success
+export async function loadConfig() {
+    const [appState, _setAppState] = appStateSig;
+    const [logArr, setLogArr] = appState().logArrSig;
+
+    let filepath = await open({
+        filters: [{
+            name: 'Config',
+            extensions: ['toml']
+        }]
+    }) as string;
+    if (filepath == null) {
+        return;
+    }
+
+    try {
+        await invoke("load_config", { path: filepath });
+    } catch (e) {
+        setLogArr([...logArr(), "failed to load config : " + e]);
+    }
+}
+
+export async function reloadConfig() {
+    const [appState, _setAppState] = appStateSig;
+    const [logArr, setLogArr] = appState().logArrSig;
+
+    try {
+        await invoke("reload_config");
+    } catch (e) {
+        setLogArr([...logArr(), "failed to reload config : " + e]);
+    }
+}
+
+// write a config for a bundled machine preset and switch to it
+export async function createConfigFromPreset(brand: string, model: string, port: string) {
+    const [appState, _setAppState] = appStateSig;
+    const [logArr, setLogArr] = appState().logArrSig;
+
+    try {
+        let path = await invoke("create_config_from_preset", { brand: brand, model: model, port: port });
+        setLogArr([...logArr(), "config created : " + path]);
+        await invoke("load_config", { path: path });
+    } catch (e) {
+        setLogArr([...logArr(), "failed to create config : " + e]);
+    }
+}